@@ -1,44 +1,387 @@
+// Tokens are kept in the system keyring (Secret Service, macOS Keychain or Windows Credential
+// Manager) where there is one. Otherwise, e.g. on a headless Linux box, each is kept in a
+// file under ~/.giant-utils which only the current user can read.
+//
+// In CI or a container, where there's nowhere to log in, the token can instead be passed
+// in a file with --token-file or in the GIANT_TOKEN environment variable.
+
 use std::{
     fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    io::{self, Read},
+    path::{Path, PathBuf},
 };
 
 use urlencoding::encode;
 
 use crate::model::cli_error::CliError;
 
-fn get_path(uri: &str) -> Result<PathBuf, CliError> {
-    if let Some(mut path) = dirs::home_dir() {
-        path.push(".giant-utils");
-        if !path.exists() {
-            fs::create_dir(&path)?;
-        }
-        let encoded_uri = encode(uri);
+const KEYRING_SERVICE: &str = "giant-utils";
+
+pub const TOKEN_ENV_VAR: &str = "GIANT_TOKEN";
+
+/// The servers we have tokens for, one URI per line, since the keyring can't be listed
+const LOGINS_FILE_NAME: &str = "logins";
 
-        path.push(encoded_uri.as_ref());
-        Ok(path)
+fn dir_path() -> Result<PathBuf, CliError> {
+    let mut path = dirs::home_dir().ok_or(CliError::UnsupportedSystem)?;
+    path.push(".giant-utils");
+    Ok(path)
+}
+
+fn get_dir() -> Result<PathBuf, CliError> {
+    let path = dir_path()?;
+    if path.exists() {
+        restrict_permissions(&path, PRIVATE_DIR_MODE)?;
     } else {
-        Err(CliError::UnsupportedSystem)
+        create_private_dir(&path)?;
     }
+    Ok(path)
 }
 
-pub fn get(uri: &str) -> Result<String, CliError> {
-    let path = get_path(uri)?;
-    let mut file = File::open(path).map_err(|e| {
-        eprintln!("Could not open auth file, have you logged in before?");
-        e
-    })?;
+fn token_path(dir: &Path, uri: &str) -> PathBuf {
+    dir.join(encode(uri).as_ref())
+}
+
+pub struct AuthToken {
+    pub token: String,
+    /// Whether it was saved by login, and so whether a refreshed token should be saved over it
+    pub stored: bool,
+}
+
+/// The token to use for this server. In order of precedence, that's the contents of
+/// `token_file`, then `env_token` from the GIANT_TOKEN environment variable, then the token
+/// saved by login.
+pub fn resolve(
+    uri: &str,
+    token_file: Option<&Path>,
+    env_token: Option<String>,
+) -> Result<AuthToken, CliError> {
+    let passed_in = match token_file {
+        Some(token_file) => Some(read_token_file(token_file)?),
+        None => env_token.and_then(non_empty),
+    };
+
+    match passed_in {
+        Some(token) => Ok(AuthToken {
+            token,
+            stored: false,
+        }),
+        None => Ok(AuthToken {
+            token: get(uri)?,
+            stored: true,
+        }),
+    }
+}
+
+pub fn read_token_file(path: &Path) -> Result<String, CliError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| CliError::InvalidToken(format!("couldn't read {}: {e}", path.display())))?;
+    non_empty(contents)
+        .ok_or_else(|| CliError::InvalidToken(format!("{} is empty", path.display())))
+}
+
+/// Tokens are often pasted or piped with a trailing newline, which would break the header
+pub fn non_empty(token: String) -> Option<String> {
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_owned())
+}
+
+/// Where a token saved by login was found
+enum StoredToken {
+    Keyring(String),
+    File(PathBuf, String),
+}
+
+fn read_stored(dir: &Path, uri: &str) -> Result<StoredToken, CliError> {
+    // Not finding it could just mean it was stored before we used the keyring,
+    // so whatever went wrong we fall back to the file
+    if let Ok(token) = keyring::Entry::new(KEYRING_SERVICE, uri).and_then(|e| e.get_password()) {
+        return Ok(StoredToken::Keyring(token));
+    }
+
+    let path = token_path(dir, uri);
+    let mut file = match File::open(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(CliError::NotLoggedIn(uri.to_owned()))
+        }
+        file => file?,
+    };
 
     let mut token = String::new();
     file.read_to_string(&mut token)?;
+    Ok(StoredToken::File(path, token))
+}
+
+pub fn get(uri: &str) -> Result<String, CliError> {
+    let dir = get_dir()?;
+    let token = match read_stored(&dir, uri)? {
+        StoredToken::Keyring(token) => token,
+        StoredToken::File(path, token) => {
+            migrate(uri, &path, &token)?;
+            token
+        }
+    };
+    // Logins from before there was an index need adding to it
+    add_login(&dir, uri)?;
 
     Ok(token)
 }
 
+/// The token saved for this server, leaving it where it is and the index as it is,
+/// for when we're only looking
+pub fn peek(uri: &str) -> Result<String, CliError> {
+    match read_stored(&dir_path()?, uri)? {
+        StoredToken::Keyring(token) | StoredToken::File(_, token) => Ok(token),
+    }
+}
+
 pub fn set(uri: &str, token: &str) -> Result<(), CliError> {
-    let path = get_path(uri)?;
-    let mut file = File::create(path)?;
+    let dir = get_dir()?;
+    let path = token_path(&dir, uri);
+    add_login(&dir, uri)?;
+
+    match keyring::Entry::new(KEYRING_SERVICE, uri).and_then(|e| e.set_password(token)) {
+        Ok(()) => {
+            // Otherwise an old token would be left lying around
+            remove_if_exists(&path)?;
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!(
+                "No system keyring available ({e}), storing the token in {}",
+                path.display()
+            );
+            write_private_file(&path, token)?;
+            // get prefers the keyring, so an old token left there would shadow this one.
+            // If the keyring is missing altogether there's nothing to delete.
+            let _ = keyring::Entry::new(KEYRING_SERVICE, uri).and_then(|e| e.delete_password());
+            Ok(())
+        }
+    }
+}
+
+/// Forget the token for this server, returning whether there was one
+pub fn delete(uri: &str) -> Result<bool, CliError> {
+    // Failing here means it wasn't there, or there's no keyring so it can only be in a file
+    let from_keyring = keyring::Entry::new(KEYRING_SERVICE, uri)
+        .and_then(|e| e.delete_password())
+        .is_ok();
+    let from_dir = delete_from_dir(&get_dir()?, uri)?;
 
-    file.write_all(token.as_bytes()).map_err(|e| e.into())
+    Ok(from_keyring || from_dir)
+}
+
+/// Remove the token file and the index entry for this server, returning whether there was either
+fn delete_from_dir(dir: &Path, uri: &str) -> Result<bool, CliError> {
+    let path = token_path(dir, uri);
+    let from_file = path.exists();
+    remove_if_exists(&path)?;
+
+    let logins = read_logins(dir)?;
+    let was_listed = logins.iter().any(|login| login == uri);
+    write_logins(dir, logins.iter().filter(|login| *login != uri))?;
+
+    Ok(from_file || was_listed)
+}
+
+/// Every server we've stored a token for
+pub fn list() -> Result<Vec<String>, CliError> {
+    read_logins(&dir_path()?)
+}
+
+fn read_logins(dir: &Path) -> Result<Vec<String>, CliError> {
+    match fs::read_to_string(dir.join(LOGINS_FILE_NAME)) {
+        Ok(contents) => Ok(contents.lines().map(str::to_owned).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn add_login(dir: &Path, uri: &str) -> Result<(), CliError> {
+    let mut logins = read_logins(dir)?;
+    if !logins.iter().any(|login| login == uri) {
+        logins.push(uri.to_owned());
+        write_logins(dir, logins.iter())?;
+    }
+    Ok(())
+}
+
+fn write_logins<'a>(dir: &Path, logins: impl Iterator<Item = &'a String>) -> Result<(), CliError> {
+    let contents: String = logins.map(|login| format!("{login}\n")).collect();
+    write_private_file(&dir.join(LOGINS_FILE_NAME), &contents).map_err(|e| e.into())
+}
+
+/// Move a token found in a file into the keyring, or if there isn't one,
+/// make sure the file is only readable by the current user
+fn migrate(uri: &str, path: &Path, token: &str) -> Result<(), CliError> {
+    match keyring::Entry::new(KEYRING_SERVICE, uri).and_then(|e| e.set_password(token)) {
+        Ok(()) => {
+            remove_if_exists(path)?;
+            eprintln!(
+                "Moved the token for {uri} from {} into the system keyring",
+                path.display()
+            );
+            Ok(())
+        }
+        Err(_) => restrict_permissions(path, PRIVATE_FILE_MODE).map_err(|e| e.into()),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+const PRIVATE_DIR_MODE: u32 = 0o700;
+const PRIVATE_FILE_MODE: u32 = 0o600;
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().mode(PRIVATE_DIR_MODE).create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir(path)
+}
+
+#[cfg(unix)]
+fn write_private_file(path: &Path, contents: &str) -> io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(PRIVATE_FILE_MODE)
+        .open(path)?;
+    // The mode only applies to new files, and this one may have been written by an older version
+    restrict_permissions(path, PRIVATE_FILE_MODE)?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private_file(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Warn about and take away any access by other users
+#[cfg(unix)]
+fn restrict_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let current = fs::metadata(path)?.permissions().mode() & 0o777;
+    if current & 0o077 != 0 {
+        eprintln!(
+            "{} was accessible to other users (mode {current:o}), restricting it to {mode:o}",
+            path.display()
+        );
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn token_files_are_only_readable_by_their_owner() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("giant-utils");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+
+        let new = dir.join("new");
+        write_private_file(&new, "token").unwrap();
+        assert_eq!(mode(&new), 0o600);
+
+        // As left by older versions
+        let old = dir.join("old");
+        fs::write(&old, "token").unwrap();
+        fs::set_permissions(&old, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_file(&old, "refreshed").unwrap();
+        assert_eq!(mode(&old), 0o600);
+        assert_eq!(fs::read_to_string(&old).unwrap(), "refreshed");
+
+        fs::set_permissions(&old, fs::Permissions::from_mode(0o640)).unwrap();
+        restrict_permissions(&old, PRIVATE_FILE_MODE).unwrap();
+        assert_eq!(mode(&old), 0o600);
+    }
+
+    #[test]
+    fn keeps_an_index_of_logins() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        assert!(read_logins(dir).unwrap().is_empty());
+
+        add_login(dir, "https://a.example.com/").unwrap();
+        add_login(dir, "https://b.example.com/").unwrap();
+        add_login(dir, "https://a.example.com/").unwrap();
+        assert_eq!(
+            read_logins(dir).unwrap(),
+            vec!["https://a.example.com/", "https://b.example.com/"]
+        );
+        assert_eq!(mode(&dir.join(LOGINS_FILE_NAME)), 0o600);
+
+        let token = token_path(dir, "https://a.example.com/");
+        write_private_file(&token, "token").unwrap();
+        assert!(delete_from_dir(dir, "https://a.example.com/").unwrap());
+        assert!(!token.exists());
+        assert_eq!(read_logins(dir).unwrap(), vec!["https://b.example.com/"]);
+        assert!(!delete_from_dir(dir, "https://a.example.com/").unwrap());
+    }
+
+    #[test]
+    fn reading_a_token_file_leaves_it_where_it_is() {
+        let tmp = tempfile::tempdir().unwrap();
+        let uri = "https://giant-utils-test.invalid/";
+        assert!(matches!(
+            read_stored(tmp.path(), uri),
+            Err(CliError::NotLoggedIn(_))
+        ));
+
+        let path = token_path(tmp.path(), uri);
+        write_private_file(&path, "token").unwrap();
+        match read_stored(tmp.path(), uri).unwrap() {
+            StoredToken::File(from, token) => {
+                assert_eq!(from, path);
+                assert_eq!(token, "token");
+            }
+            StoredToken::Keyring(_) => panic!("there's nothing in the keyring for {uri}"),
+        }
+        assert!(path.exists());
+        assert!(read_logins(tmp.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn token_file_takes_precedence_over_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "Bearer from-file\n").unwrap();
+        let env_token = || Some("Bearer from-env\n".to_owned());
+
+        let from_file = resolve("https://giant.example.com/", Some(&path), env_token()).unwrap();
+        assert_eq!(from_file.token, "Bearer from-file");
+        assert!(!from_file.stored);
+
+        let from_env = resolve("https://giant.example.com/", None, env_token()).unwrap();
+        assert_eq!(from_env.token, "Bearer from-env");
+        assert!(!from_env.stored);
+    }
 }
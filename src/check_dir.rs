@@ -0,0 +1,147 @@
+// Hashes every file under some paths and checks each against Giant, so that we can prove
+// a drive has been fully ingested without re-uploading it.
+
+use std::{future::Future, path::PathBuf};
+
+use futures::{stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::{
+    hash::hash_paths,
+    model::{
+        check_dir_summary::CheckDirSummary,
+        checked_file::{CheckedFile, HashStatus},
+        cli_error::CliError,
+        cli_output::OutputFormat,
+    },
+};
+
+/// Hashed files waiting to be checked, so hashing can get ahead of slow checks without
+/// holding a whole drive's worth in memory
+const CHECK_QUEUE_SIZE: usize = 10_000;
+
+/// Print whether Giant has each file as soon as we know, returning how many of each there were.
+/// `check` asks Giant about a single hash.
+pub async fn check_dir<F, Fut>(
+    check: F,
+    paths: Vec<PathBuf>,
+    hash_threads: usize,
+    num_parallel_checks: usize,
+    format: &OutputFormat,
+) -> Result<CheckDirSummary, CliError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<HashStatus, CliError>>,
+{
+    let (sender, receiver) = mpsc::channel(CHECK_QUEUE_SIZE);
+    let hashing = tokio::task::spawn_blocking(move || {
+        hash_paths(&paths, hash_threads, |result| {
+            // Only fails if we've given up on checking, so there's no point hashing the rest
+            sender.blocking_send(result).is_ok()
+        })
+    });
+
+    let check = &check;
+    let hashed = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|result| (result, receiver))
+    });
+    let mut checks = Box::pin(
+        hashed
+            .map(|result| async move {
+                match result {
+                    Ok(file) => match check(file.hash.clone()).await {
+                        Ok(status) => Ok((file.path, file.hash, status)),
+                        Err(e) => Err((file.path, e.to_string(), e)),
+                    },
+                    Err((path, e)) => Err((path, e.to_string(), CliError::Io(e))),
+                }
+            })
+            .buffer_unordered(num_parallel_checks),
+    );
+
+    let mut summary = CheckDirSummary::default();
+    while let Some(result) = checks.next().await {
+        match result {
+            Ok((path, hash, status)) => {
+                match status {
+                    HashStatus::Present => summary.present += 1,
+                    HashStatus::Missing => summary.missing += 1,
+                    HashStatus::Forbidden => summary.forbidden += 1,
+                }
+                format.print_item(&CheckedFile {
+                    status: status.as_str().to_owned(),
+                    hash,
+                    path: path.display().to_string(),
+                });
+            }
+            // Every other check would fail the same way
+            Err((_, _, CliError::APIAuthError)) => return Err(CliError::APIAuthError),
+            Err((path, reason, _)) => {
+                summary.failed += 1;
+                eprintln!("Failed to check {}: {reason}", path.display());
+            }
+        }
+    }
+    drop(checks);
+    if let Err(e) = hashing.await {
+        // We can't know what it didn't get to, so don't claim everything's there
+        eprintln!("Hashing stopped early: {e}");
+        summary.failed += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use crate::hash::hash_path;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_each_status_and_unreadable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut statuses = HashMap::new();
+        for (name, status) in [
+            ("present", HashStatus::Present),
+            ("missing", HashStatus::Missing),
+            ("forbidden", HashStatus::Forbidden),
+        ] {
+            let path = dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            statuses.insert(hash_path(&path).unwrap(), status);
+        }
+
+        let check = |hash: String| {
+            let status = statuses[&hash];
+            async move { Ok(status) }
+        };
+        let paths = vec![dir.path().to_owned(), dir.path().join("gone")];
+        let summary = check_dir(check, paths, 2, 2, &OutputFormat::Tsv)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            (
+                summary.present,
+                summary.missing,
+                summary.forbidden,
+                summary.failed
+            ),
+            (1, 1, 1, 1)
+        );
+        assert!(!summary.all_present());
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_giant_rejects_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file"), "file").unwrap();
+
+        let check = |_| async { Err(CliError::APIAuthError) };
+        let result = check_dir(check, vec![dir.path().to_owned()], 1, 1, &OutputFormat::Tsv).await;
+        assert!(matches!(result, Err(CliError::APIAuthError)));
+    }
+}
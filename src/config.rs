@@ -0,0 +1,174 @@
+// Named profiles in ~/.giant-utils/config.toml, so that the Giant server and S3 settings
+// don't have to be repeated on every command. For example:
+//
+//   default_profile = "prod"
+//
+//   [profiles.prod]
+//   giant_uri = "https://giant.example.com"
+//   bucket = "giant-ingestion"
+//   region = "eu-west-1"
+//   aws_profile = "investigations"
+//   num_parallel_uploads = 16
+//   format = "json"
+//
+// Anything given on the command line takes precedence over the profile.
+
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::model::{cli_error::CliError, cli_output::OutputFormat};
+
+pub const PROFILE_ENV_VAR: &str = "GIANT_PROFILE";
+
+/// Used when neither the config file nor the command line picks a profile
+const DEFAULT_PROFILE_NAME: &str = "default";
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    giant_uri: Option<String>,
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    pub aws_profile: Option<String>,
+    s3_endpoint: Option<String>,
+    pub num_parallel_uploads: Option<usize>,
+    pub format: Option<OutputFormat>,
+}
+
+impl Profile {
+    pub fn giant_uri(&self) -> Result<Option<Url>, CliError> {
+        self.giant_uri
+            .as_deref()
+            .map(|uri| parse_giant_uri(uri).map_err(CliError::Config))
+            .transpose()
+    }
+
+    pub fn s3_endpoint(&self) -> Result<Option<http::Uri>, CliError> {
+        self.s3_endpoint
+            .as_deref()
+            .map(|endpoint| {
+                endpoint
+                    .parse()
+                    .map_err(|e| CliError::Config(format!("invalid s3_endpoint '{endpoint}': {e}")))
+            })
+            .transpose()
+    }
+}
+
+/// Only http(s) URLs, so that a path can't be mistaken for a Giant URI
+pub fn parse_giant_uri(uri: &str) -> Result<Url, String> {
+    match Url::parse(uri) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+        Ok(url) => Err(format!(
+            "'{uri}' should be an http or https URL, not {}",
+            url.scheme()
+        )),
+        Err(e) => Err(format!("'{uri}' isn't a URL: {e}")),
+    }
+}
+
+pub fn config_path() -> Result<PathBuf, CliError> {
+    let mut path = dirs::home_dir().ok_or(CliError::UnsupportedSystem)?;
+    path.push(".giant-utils");
+    path.push("config.toml");
+    Ok(path)
+}
+
+/// Load the profile chosen with --profile or GIANT_PROFILE, falling back to the config's
+/// default. It's only an error for there to be no such profile if one was asked for.
+pub fn load_profile(requested: Option<&str>) -> Result<Profile, CliError> {
+    let requested = requested
+        .map(str::to_owned)
+        .or_else(|| std::env::var(PROFILE_ENV_VAR).ok());
+
+    let path = config_path()?;
+    let config = match fs::read_to_string(&path) {
+        Ok(contents) => parse_config(&contents)
+            .map_err(|e| CliError::Config(format!("{}: {e}", path.display())))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    select_profile(config, requested)
+}
+
+fn parse_config(contents: &str) -> Result<Config, String> {
+    let config: Config = toml::from_str(contents).map_err(|e| e.to_string())?;
+    // Catch a bad URI now, rather than when it's first used
+    for profile in config.profiles.values() {
+        profile.giant_uri().map_err(|e| e.to_string())?;
+    }
+    Ok(config)
+}
+
+fn select_profile(mut config: Config, requested: Option<String>) -> Result<Profile, CliError> {
+    match requested.or(config.default_profile.take()) {
+        Some(name) => config.profiles.remove(&name).ok_or_else(|| {
+            let mut names: Vec<_> = config.profiles.keys().cloned().collect();
+            names.sort();
+            CliError::Config(format!(
+                "no profile called '{name}', the profiles are: {}",
+                names.join(", ")
+            ))
+        }),
+        None => Ok(config
+            .profiles
+            .remove(DEFAULT_PROFILE_NAME)
+            .unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        default_profile = "prod"
+
+        [profiles.prod]
+        giant_uri = "https://giant.example.com"
+        bucket = "ingestion"
+        format = "json"
+
+        [profiles.local]
+        giant_uri = "http://localhost:9001"
+    "#;
+
+    #[test]
+    fn selects_the_requested_or_default_profile() {
+        let prod = select_profile(parse_config(CONFIG).unwrap(), None).unwrap();
+        assert_eq!(prod.bucket.as_deref(), Some("ingestion"));
+        assert!(matches!(prod.format, Some(OutputFormat::Json)));
+
+        let local = select_profile(parse_config(CONFIG).unwrap(), Some("local".into())).unwrap();
+        assert_eq!(
+            local.giant_uri().unwrap().unwrap().as_str(),
+            "http://localhost:9001/"
+        );
+
+        let missing = select_profile(parse_config(CONFIG).unwrap(), Some("staging".into()));
+        assert!(
+            matches!(missing, Err(CliError::Config(message)) if message.contains("local, prod"))
+        );
+    }
+
+    #[test]
+    fn rejects_paths_and_typos() {
+        assert!(parse_giant_uri("c:/data").is_err());
+        assert!(parse_giant_uri("collection").is_err());
+        assert!(
+            parse_config("[profiles.prod]\ngiant_url = \"https://giant.example.com\"").is_err()
+        );
+        assert!(parse_config("[profiles.prod]\ngiant_uri = \"giant.example.com\"").is_err());
+    }
+}
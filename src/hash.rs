@@ -1,33 +1,184 @@
 use std::{
     fs::File,
-    io::{BufReader, Read},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    thread,
+    time::SystemTime,
 };
 
 use sha2::{Digest, Sha512};
+use walkdir::WalkDir;
 
 use crate::model::{cli_error::CliError, hash_file_output::HashFileOutput};
 
+/// Files are read this much at a time, enough to keep fast disks busy
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
 pub fn hash_file(path: String) -> Result<HashFileOutput, CliError> {
-    let f = File::open(&path)?;
-    let mut reader = BufReader::new(f);
+    let encoded_digest = hash_path(&path)?;
+
+    Ok(HashFileOutput::new(encoded_digest, path))
+}
+
+/// Produce the Giant ID for a file, the URL safe base64 of its SHA-512
+pub fn hash_path(path: impl AsRef<Path>) -> Result<String, CliError> {
+    hash_path_with(path, |_| {})
+}
+
+/// Like hash_path, but also passes everything read to `on_chunk`, so that
+/// other checksums of the file can be computed without reading it again
+pub fn hash_path_with(
+    path: impl AsRef<Path>,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> Result<String, CliError> {
+    let mut f = File::open(path)?;
 
     let mut hasher = Sha512::new();
 
-    let mut buf = [0u8; 512];
+    let mut buf = vec![0u8; HASH_BUFFER_SIZE];
 
     loop {
-        let byte_count = reader.read(&mut buf)?;
+        let byte_count = f.read(&mut buf)?;
 
         if byte_count == 0 {
             break;
         }
 
         hasher.update(&buf[..byte_count]);
+        on_chunk(&buf[..byte_count]);
     }
 
     let digest = hasher.finalize();
 
-    let encoded_digest = base64::encode_config(digest, base64::URL_SAFE_NO_PAD);
+    Ok(base64::encode_config(digest, base64::URL_SAFE_NO_PAD))
+}
 
-    Ok(HashFileOutput::new(encoded_digest, path))
+pub struct HashedFile {
+    pub path: PathBuf,
+    pub hash: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Hash every file in or under the given paths using `threads` threads, passing each
+/// to `on_file` as soon as it's done, until it returns false. Files that can't be read
+/// are passed on as errors rather than stopping the rest.
+pub fn hash_paths(
+    paths: &[PathBuf],
+    threads: usize,
+    mut on_file: impl FnMut(Result<HashedFile, (PathBuf, io::Error)>) -> bool,
+) {
+    let files = Mutex::new(
+        paths
+            .iter()
+            .flat_map(WalkDir::new)
+            // Symlinks are only followed if they're one of the paths we were given
+            .filter(|entry| match entry {
+                Ok(entry) => entry.file_type().is_file(),
+                Err(_) => true,
+            }),
+    );
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let files = &files;
+            scope.spawn(move || loop {
+                // Only hold the lock while finding the next file, not while hashing it
+                let next = files.lock().unwrap().next();
+                let result = match next {
+                    Some(Ok(entry)) => hash_entry(entry.into_path()),
+                    Some(Err(e)) => {
+                        let path = e.path().map(Path::to_owned).unwrap_or_default();
+                        Err((path, e.into()))
+                    }
+                    None => break,
+                };
+                if sender.send(result).is_err() {
+                    break;
+                }
+            });
+        }
+        // Otherwise the receiver would never see the channel close
+        drop(sender);
+
+        // Dropping the receiver stops each thread once it's finished its current file
+        for result in receiver {
+            if !on_file(result) {
+                break;
+            }
+        }
+    });
+}
+
+fn hash_entry(path: PathBuf) -> Result<HashedFile, (PathBuf, io::Error)> {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) => return Err((path, e)),
+    };
+    match hash_path(&path) {
+        Ok(hash) => Ok(HashedFile {
+            hash,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            path,
+        }),
+        Err(CliError::Io(e)) => Err((path, e)),
+        Err(e) => Err((path, io::Error::other(e.to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn hashes_every_file_under_each_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("nested/b.txt"), "b").unwrap();
+        let single = tmp.path().join("single.txt");
+        fs::write(&single, "a").unwrap();
+
+        let mut hashed = vec![];
+        hash_paths(&[dir.clone(), single.clone()], 4, |result| {
+            let file = result.unwrap();
+            hashed.push((file.path, file.hash, file.size));
+            true
+        });
+        hashed.sort();
+
+        let mut expected = vec![
+            (dir.join("a.txt"), hash_path(&single).unwrap(), 1),
+            (
+                dir.join("nested/b.txt"),
+                hash_path(dir.join("nested/b.txt")).unwrap(),
+                1,
+            ),
+            (single.clone(), hash_path(&single).unwrap(), 1),
+        ];
+        expected.sort();
+        assert_eq!(hashed, expected);
+    }
+
+    #[test]
+    fn stops_once_told_to() {
+        let tmp = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            fs::write(tmp.path().join(i.to_string()), "a").unwrap();
+        }
+
+        let mut calls = 0;
+        hash_paths(&[tmp.path().to_owned()], 4, |_| {
+            calls += 1;
+            false
+        });
+        assert_eq!(calls, 1);
+    }
 }
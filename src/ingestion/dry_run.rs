@@ -0,0 +1,351 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use futures::{stream, StreamExt};
+use indicatif::{HumanBytes, ProgressBar};
+
+use crate::{
+    ingestion::{
+        file_filter::{FileFilter, WalkItem, Walker},
+        ingestion_upload::hash_in_background,
+    },
+    model::{
+        cli_error::CliError, dry_run_summary::DryRunSummary, ingestion_file::IngestionFile,
+        uri::Uri,
+    },
+    services::giant_api::GiantApiClient,
+};
+
+const DEEPEST_PATHS_SHOWN: usize = 10;
+
+/// Files found by the walk but not yet tallied
+const WALK_QUEUE_SIZE: usize = 100_000;
+
+#[derive(Default)]
+struct DryRunReport {
+    total_files: u64,
+    total_bytes: u64,
+    excluded_files: u64,
+    excluded_dirs: u64,
+    // Min-heap on depth, so the shallowest of the deepest paths is the one to drop
+    deepest_paths: BinaryHeap<Reverse<(usize, PathBuf)>>,
+    // The first file seen for each URI, any others are collisions
+    first_path_by_uri: HashMap<String, PathBuf>,
+    // Every file for each URI that more than one file would be uploaded to
+    collisions: HashMap<String, Vec<PathBuf>>,
+    unreadable: Vec<(PathBuf, String)>,
+    // Readable files and their sizes, only kept if they're going to be checked against Giant
+    readable: Vec<(PathBuf, u64)>,
+}
+
+impl DryRunReport {
+    /// Every URI more than one file would be uploaded to, with those files, sorted by URI
+    fn collisions(&self) -> Vec<(&str, &[PathBuf])> {
+        let mut collisions: Vec<(&str, &[PathBuf])> = self
+            .collisions
+            .iter()
+            .map(|(uri, paths)| (uri.as_str(), paths.as_slice()))
+            .collect();
+        collisions.sort();
+        collisions
+    }
+
+    fn deepest_paths(&self) -> Vec<(usize, &Path)> {
+        let mut deepest: Vec<(usize, &Path)> = self
+            .deepest_paths
+            .iter()
+            .map(|Reverse((depth, path))| (*depth, path.as_path()))
+            .collect();
+        deepest.sort_by(|a, b| b.cmp(a));
+        deepest
+    }
+}
+
+/// Walk an ingestion with the same filters as a real run and report what it would upload,
+/// without creating the collection or ingestion or touching S3. If a Giant client is given,
+/// each file is also hashed to count how many Giant already has.
+pub async fn dry_run(
+    ingestion_uri: &Uri,
+    path: impl AsRef<Path>,
+    filter: &FileFilter,
+    walker: Walker,
+    walker_threads: usize,
+    check_existing: Option<&GiantApiClient>,
+    num_parallel_checks: usize,
+) -> Result<DryRunSummary, CliError> {
+    eprintln!("Dry run, nothing will be uploaded");
+    let path = path.as_ref();
+    let (sender, receiver) = mpsc::sync_channel(WALK_QUEUE_SIZE);
+    let mut report = thread::scope(|scope| {
+        scope.spawn(move || {
+            filter.walk_with(walker, walker_threads, path, |item| {
+                sender.send(item).is_ok()
+            })
+        });
+        plan(ingestion_uri, path, receiver, check_existing.is_some())
+    });
+
+    eprintln!(
+        "  Files: {} ({})",
+        report.total_files,
+        HumanBytes(report.total_bytes)
+    );
+    if report.excluded_files > 0 || report.excluded_dirs > 0 {
+        eprintln!(
+            "  Excluded: {} files, {} directories",
+            report.excluded_files, report.excluded_dirs
+        );
+    }
+
+    let deepest_paths = report.deepest_paths();
+    eprintln!("  Deepest paths:");
+    for (depth, path) in &deepest_paths {
+        eprintln!("    {depth}\t{}", path.display());
+    }
+
+    let collisions = report.collisions();
+    eprintln!("  URI collisions: {}", collisions.len());
+    for (uri, paths) in &collisions {
+        eprintln!("    {uri}");
+        for path in *paths {
+            eprintln!("      {}", path.display());
+        }
+    }
+
+    eprintln!("  Unreadable: {}", report.unreadable.len());
+    for (path, reason) in &report.unreadable {
+        eprintln!("    {}: {reason}", path.display());
+    }
+
+    let mut summary = DryRunSummary {
+        files: report.total_files,
+        bytes: report.total_bytes,
+        excluded_files: report.excluded_files,
+        excluded_dirs: report.excluded_dirs,
+        max_depth: deepest_paths.first().map_or(0, |(depth, _)| *depth as u64),
+        uri_collisions: collisions.len() as u64,
+        unreadable: report.unreadable.len() as u64,
+        ..DryRunSummary::default()
+    };
+
+    if let Some(giant_client) = check_existing {
+        eprintln!("Hashing files to check which are already in Giant");
+        let readable = std::mem::take(&mut report.readable);
+        let pb = ProgressBar::new(readable.len() as u64);
+        giant_client.set_progress_bar(&pb);
+
+        let checks = stream::iter(readable)
+            .map(|(path, size)| {
+                let pb = &pb;
+                async move {
+                    let result = async {
+                        let hash = hash_in_background(&path).await?;
+                        anyhow::Ok(giant_client.check_hash_exists(&hash).await?)
+                    }
+                    .await;
+                    pb.inc(1);
+                    (path, size, result)
+                }
+            })
+            .buffer_unordered(num_parallel_checks)
+            .collect::<Vec<_>>()
+            .await;
+        pb.finish_and_clear();
+
+        let mut existing_count = 0;
+        let mut existing_bytes = 0;
+        let mut failed_checks = 0;
+        for (path, size, result) in checks {
+            match result {
+                Ok(true) => {
+                    existing_count += 1;
+                    existing_bytes += size;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Couldn't check {}: {e}", path.display());
+                    failed_checks += 1;
+                }
+            }
+        }
+
+        eprintln!(
+            "  Already in Giant: {existing_count} ({})",
+            HumanBytes(existing_bytes)
+        );
+        if failed_checks > 0 {
+            eprintln!("  Couldn't check: {failed_checks}");
+        }
+        summary.checked_existing = true;
+        summary.already_in_giant = existing_count;
+        summary.bytes_already_in_giant = existing_bytes;
+        summary.failed_checks = failed_checks;
+    }
+
+    Ok(summary)
+}
+
+/// Tally up what the walk found. Readable files are only kept if `keep_readable`,
+/// since there can be millions of them.
+fn plan(
+    ingestion_uri: &Uri,
+    path: impl AsRef<Path>,
+    items: impl IntoIterator<Item = WalkItem>,
+    keep_readable: bool,
+) -> DryRunReport {
+    let mut report = DryRunReport::default();
+
+    for item in items {
+        match item {
+            WalkItem::File(file) => {
+                report.total_files += 1;
+
+                // Anything that fails here would fail during a real ingestion
+                let ingestion_file =
+                    match IngestionFile::from_file(ingestion_uri, &path, &file.path) {
+                        Ok(ingestion_file) => ingestion_file,
+                        Err(e) => {
+                            report.unreadable.push((file.path.clone(), e.to_string()));
+                            continue;
+                        }
+                    };
+                if let Err(e) = File::open(&file.path) {
+                    report.unreadable.push((file.path.clone(), e.to_string()));
+                    continue;
+                }
+
+                report.total_bytes += ingestion_file.size;
+                let uri = ingestion_file.uri.as_str();
+                if let Some(first_path) = report.first_path_by_uri.get(uri) {
+                    report
+                        .collisions
+                        .entry(uri.to_owned())
+                        .or_insert_with(|| vec![first_path.clone()])
+                        .push(file.path.clone());
+                } else {
+                    report
+                        .first_path_by_uri
+                        .insert(uri.to_owned(), file.path.clone());
+                }
+
+                report
+                    .deepest_paths
+                    .push(Reverse((file.depth, file.path.clone())));
+                if report.deepest_paths.len() > DEEPEST_PATHS_SHOWN {
+                    report.deepest_paths.pop();
+                }
+
+                if keep_readable {
+                    report.readable.push((file.path, ingestion_file.size));
+                }
+            }
+            WalkItem::Excluded { is_dir: true, .. } => report.excluded_dirs += 1,
+            WalkItem::Excluded { is_dir: false, .. } => report.excluded_files += 1,
+            WalkItem::Error { path, reason } => report.unreadable.push((path, reason)),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::ingestion::file_filter::{Exclusion, WalkedFile};
+
+    use super::*;
+
+    fn file(base: &Path, relative_path: &str) -> WalkItem {
+        let path = base.join(relative_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, relative_path).unwrap();
+        WalkItem::File(WalkedFile {
+            depth: path.strip_prefix(base).unwrap().components().count(),
+            path,
+        })
+    }
+
+    #[test]
+    fn keeps_only_the_deepest_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let items: Vec<WalkItem> = (1..=DEEPEST_PATHS_SHOWN + 2)
+            .map(|depth| file(dir.path(), &format!("{}f", "d/".repeat(depth - 1))))
+            .collect();
+
+        let report = plan(&Uri::from("c/i"), dir.path(), items, false);
+        let deepest = report.deepest_paths();
+        assert_eq!(deepest.len(), DEEPEST_PATHS_SHOWN);
+        assert_eq!(deepest[0].0, DEEPEST_PATHS_SHOWN + 2);
+        assert_eq!(deepest.last().unwrap().0, 3);
+        assert_eq!(report.total_files, DEEPEST_PATHS_SHOWN as u64 + 2);
+        // Not checking against Giant, so there's no need to hold onto them
+        assert!(report.readable.is_empty());
+    }
+
+    #[test]
+    fn counts_unreadable_and_excluded() {
+        let dir = tempfile::tempdir().unwrap();
+        let items = vec![
+            file(dir.path(), "readable"),
+            // Gone by the time it's looked at
+            WalkItem::File(WalkedFile {
+                path: dir.path().join("deleted"),
+                depth: 1,
+            }),
+            WalkItem::Error {
+                path: dir.path().join("locked"),
+                reason: "Permission denied".into(),
+            },
+            WalkItem::Excluded {
+                path: dir.path().join(".git"),
+                is_dir: true,
+                exclusion: Exclusion::Pattern(".git".into()),
+            },
+        ];
+
+        let report = plan(&Uri::from("c/i"), dir.path(), items, true);
+        assert_eq!(report.total_files, 2);
+        assert_eq!(report.total_bytes, "readable".len() as u64);
+        assert_eq!(report.excluded_dirs, 1);
+        let unreadable: Vec<&Path> = report.unreadable.iter().map(|(p, _)| p.as_path()).collect();
+        assert_eq!(
+            unreadable,
+            vec![dir.path().join("deleted"), dir.path().join("locked")]
+        );
+        assert_eq!(
+            report.readable,
+            vec![(dir.path().join("readable"), "readable".len() as u64)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_files_which_would_get_the_same_uri() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        // Names that aren't valid UTF-8 are displayed lossily, so can end up the same
+        let names = [&b"caf\xe9"[..], &b"caf\xe8"[..], &b"cafe"[..]];
+        let items: Vec<WalkItem> = names
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(OsStr::from_bytes(name));
+                fs::write(&path, "").unwrap();
+                WalkItem::File(WalkedFile { path, depth: 1 })
+            })
+            .collect();
+
+        let report = plan(&Uri::from("c/i"), dir.path(), items, false);
+        let collisions = report.collisions();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].0, "c/i/caf\u{FFFD}");
+        assert_eq!(collisions[0].1.len(), 2);
+    }
+}
@@ -0,0 +1,620 @@
+use std::{
+    fmt, iter,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match, WalkBuilder, WalkState,
+};
+use walkdir::WalkDir;
+
+use crate::model::cli_error::CliError;
+
+/// Honoured in every directory of an ingestion, using .gitignore syntax
+pub const IGNORE_FILE_NAME: &str = ".giantignore";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExcludePreset {
+    /// Finder, Spotlight and Windows Explorer droppings, e.g. .DS_Store and Thumbs.db
+    OsJunk,
+    /// Version control directories, e.g. .git
+    Vcs,
+    /// Lock files left by open Office and LibreOffice documents, e.g. ~$report.docx
+    OfficeLocks,
+    /// Anything whose name starts with a dot
+    Hidden,
+}
+
+impl ExcludePreset {
+    /// Matched case insensitively against file and directory names
+    fn patterns(&self) -> &'static [&'static str] {
+        match self {
+            Self::OsJunk => &[
+                ".DS_Store",
+                "._*",
+                ".Spotlight-V100",
+                ".Trashes",
+                ".fseventsd",
+                ".TemporaryItems",
+                ".DocumentRevisions-V100",
+                "Thumbs.db",
+                "ehthumbs.db",
+                "desktop.ini",
+                "$RECYCLE.BIN",
+                "System Volume Information",
+            ],
+            Self::Vcs => &[".git", ".svn", ".hg", ".bzr", "CVS"],
+            Self::OfficeLocks => &["~$*", ".~lock.*#"],
+            Self::Hidden => &[".*"],
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::OsJunk => "os-junk",
+            Self::Vcs => "vcs",
+            Self::OfficeLocks => "office-locks",
+            Self::Hidden => "hidden",
+        }
+    }
+}
+
+pub struct FileFilterOptions {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub presets: Vec<ExcludePreset>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub use_ignore_files: bool,
+}
+
+impl Default for FileFilterOptions {
+    fn default() -> Self {
+        FileFilterOptions {
+            include: vec![],
+            exclude: vec![],
+            presets: vec![],
+            min_size: None,
+            max_size: None,
+            use_ignore_files: true,
+        }
+    }
+}
+
+/// Why a file or directory wasn't ingested
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exclusion {
+    Pattern(String),
+    Preset(ExcludePreset),
+    IgnoreFile(PathBuf),
+    NotIncluded,
+    TooSmall(u64),
+    TooLarge(u64),
+}
+
+impl fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pattern(pattern) => write!(f, "matched --exclude '{pattern}'"),
+            Self::Preset(preset) => write!(f, "matched --exclude-preset {}", preset.name()),
+            Self::IgnoreFile(path) => write!(f, "matched {}", path.display()),
+            Self::NotIncluded => write!(f, "didn't match any --include"),
+            Self::TooSmall(size) => write!(f, "smaller than --min-size ({size} bytes)"),
+            Self::TooLarge(size) => write!(f, "larger than --max-size ({size} bytes)"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Walker {
+    /// One directory at a time, kindest to spinning disks
+    Sequential,
+    /// Several directories at once, faster on SSDs and network drives.
+    /// Files left out by a .giantignore aren't reported as excluded.
+    Parallel,
+}
+
+pub struct WalkedFile {
+    pub path: PathBuf,
+    /// 1 for files directly inside the base path
+    pub depth: usize,
+}
+
+pub enum WalkItem {
+    File(WalkedFile),
+    /// Excluded directories aren't descended into, so their contents don't appear
+    Excluded {
+        path: PathBuf,
+        is_dir: bool,
+        exclusion: Exclusion,
+    },
+    Error {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+pub struct FileFilter {
+    include: Option<(GlobSet, Vec<String>)>,
+    exclude: (GlobSet, Vec<String>),
+    presets: Vec<(GlobSet, ExcludePreset)>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    use_ignore_files: bool,
+}
+
+impl FileFilter {
+    pub fn new(options: FileFilterOptions) -> Result<Self, CliError> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some((build_glob_set(&options.include, false)?, options.include))
+        };
+        let exclude = (build_glob_set(&options.exclude, false)?, options.exclude);
+
+        let presets = options
+            .presets
+            .into_iter()
+            .map(|preset| {
+                let patterns: Vec<String> =
+                    preset.patterns().iter().map(|p| p.to_string()).collect();
+                Ok((build_glob_set(&patterns, true)?, preset))
+            })
+            .collect::<Result<_, CliError>>()?;
+
+        Ok(FileFilter {
+            include,
+            exclude,
+            presets,
+            min_size: options.min_size,
+            max_size: options.max_size,
+            use_ignore_files: options.use_ignore_files,
+        })
+    }
+
+    /// Walk every file under `path` which isn't a directory or a symlink, reporting those excluded
+    pub fn walk<'a>(&'a self, path: impl AsRef<Path>) -> FilteredWalk<'a> {
+        FilteredWalk {
+            filter: self,
+            base_path: path.as_ref().to_owned(),
+            inner: WalkDir::new(path).into_iter(),
+            ignore_files: vec![],
+        }
+    }
+
+    fn check_name(&self, relative_path: &Path, file_name: &Path) -> Option<Exclusion> {
+        if let Some((_, preset)) = self
+            .presets
+            .iter()
+            .find(|(globs, _)| globs.is_match(file_name))
+        {
+            return Some(Exclusion::Preset(*preset));
+        }
+
+        let (globs, patterns) = &self.exclude;
+        globs
+            .matches(relative_path)
+            .into_iter()
+            .chain(globs.matches(file_name))
+            .next()
+            .map(|i| Exclusion::Pattern(patterns[i].clone()))
+    }
+
+    fn check_file(
+        &self,
+        relative_path: &Path,
+        file_name: &Path,
+        size: Option<u64>,
+    ) -> Option<Exclusion> {
+        if let Some((globs, _)) = &self.include {
+            if !globs.is_match(relative_path) && !globs.is_match(file_name) {
+                return Some(Exclusion::NotIncluded);
+            }
+        }
+
+        // Files we can't stat are left for the upload to report
+        let size = size?;
+        match (self.min_size, self.max_size) {
+            (Some(min), _) if size < min => Some(Exclusion::TooSmall(min)),
+            (_, Some(max)) if size > max => Some(Exclusion::TooLarge(max)),
+            _ => None,
+        }
+    }
+}
+
+impl FileFilter {
+    /// Whether to leave out an entry, given the .giantignore files of the directories
+    /// it's inside, shallowest first
+    fn check_entry<'g>(
+        &self,
+        ignore_files: impl DoubleEndedIterator<Item = &'g Gitignore>,
+        path: &Path,
+        relative_path: &Path,
+        is_dir: bool,
+        size: impl FnOnce() -> Option<u64>,
+    ) -> Option<Exclusion> {
+        let file_name = path.file_name().map(Path::new).unwrap_or(path);
+
+        self.check_name(relative_path, file_name)
+            .or_else(|| check_ignore_files(ignore_files, path, is_dir))
+            .or_else(|| {
+                if is_dir {
+                    None
+                } else if self.use_ignore_files && file_name == IGNORE_FILE_NAME {
+                    Some(Exclusion::IgnoreFile(path.to_owned()))
+                } else {
+                    self.check_file(relative_path, file_name, size())
+                }
+            })
+    }
+
+    fn load_ignore_file(&self, dir: &Path) -> Option<Gitignore> {
+        let ignore_path = dir.join(IGNORE_FILE_NAME);
+        if !self.use_ignore_files || !ignore_path.is_file() {
+            return None;
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&ignore_path) {
+            eprintln!("Problem reading {}: {e}", ignore_path.display());
+        }
+        match builder.build() {
+            Ok(ignore_file) => Some(ignore_file),
+            Err(e) => {
+                eprintln!("Ignoring {}: {e}", ignore_path.display());
+                None
+            }
+        }
+    }
+
+    /// Walk every file under `path` with the given walker, handing each item to `send`
+    /// until it returns false. Items from the parallel walker arrive in no particular order.
+    pub fn walk_with(
+        &self,
+        walker: Walker,
+        threads: usize,
+        path: &Path,
+        send: impl Fn(WalkItem) -> bool + Sync,
+    ) {
+        match walker {
+            Walker::Sequential => {
+                for item in self.walk(path) {
+                    if !send(item) {
+                        return;
+                    }
+                }
+            }
+            Walker::Parallel => self.walk_parallel(path, threads, &send),
+        }
+    }
+
+    fn walk_parallel(
+        &self,
+        base_path: &Path,
+        threads: usize,
+        send: &(impl Fn(WalkItem) -> bool + Sync),
+    ) {
+        let mut builder = WalkBuilder::new(base_path);
+        builder.standard_filters(false).threads(threads.max(1));
+        if self.use_ignore_files {
+            builder.add_custom_ignore_filename(IGNORE_FILE_NAME);
+        }
+
+        builder.build_parallel().run(|| {
+            Box::new(|result| {
+                let entry = match result {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        let path = error_path(&e).unwrap_or(base_path).to_owned();
+                        let reason = e.to_string();
+                        return match send(WalkItem::Error { path, reason }) {
+                            true => WalkState::Continue,
+                            false => WalkState::Quit,
+                        };
+                    }
+                };
+                if entry.depth() == 0 || entry.path_is_symlink() {
+                    return WalkState::Continue;
+                }
+
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                let relative_path = entry.path().strip_prefix(base_path).unwrap_or(entry.path());
+                // The walker applies the .giantignore files itself
+                let exclusion =
+                    self.check_entry(iter::empty(), entry.path(), relative_path, is_dir, || {
+                        entry.metadata().ok().map(|m| m.len())
+                    });
+
+                let (item, next) = match exclusion {
+                    Some(exclusion) => (
+                        WalkItem::Excluded {
+                            path: entry.path().to_owned(),
+                            is_dir,
+                            exclusion,
+                        },
+                        WalkState::Skip,
+                    ),
+                    None if is_dir => return WalkState::Continue,
+                    None => (
+                        WalkItem::File(WalkedFile {
+                            depth: entry.depth(),
+                            path: entry.into_path(),
+                        }),
+                        WalkState::Continue,
+                    ),
+                };
+
+                match send(item) {
+                    true => next,
+                    false => WalkState::Quit,
+                }
+            })
+        });
+    }
+}
+
+fn error_path(e: &ignore::Error) -> Option<&Path> {
+    match e {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            error_path(err)
+        }
+        _ => None,
+    }
+}
+
+fn check_ignore_files<'g>(
+    ignore_files: impl DoubleEndedIterator<Item = &'g Gitignore>,
+    path: &Path,
+    is_dir: bool,
+) -> Option<Exclusion> {
+    // The deepest .giantignore with an opinion wins, so it can re-include with '!'
+    for ignore_file in ignore_files.rev() {
+        match ignore_file.matched(path, is_dir) {
+            Match::Ignore(glob) => {
+                let source = glob
+                    .from()
+                    .map(|p| p.to_owned())
+                    .unwrap_or_else(|| PathBuf::from(IGNORE_FILE_NAME));
+                return Some(Exclusion::IgnoreFile(source));
+            }
+            Match::Whitelist(_) => return None,
+            Match::None => {}
+        }
+    }
+    None
+}
+
+fn build_glob_set(patterns: &[String], case_insensitive: bool) -> Result<GlobSet, CliError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = if case_insensitive {
+            GlobBuilder::new(pattern).case_insensitive(true).build()
+        } else {
+            Glob::new(pattern)
+        }
+        .map_err(|e| CliError::InputError(format!("Invalid glob '{pattern}': {e}")))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| CliError::InputError(format!("Invalid globs: {e}")))
+}
+
+pub struct FilteredWalk<'a> {
+    filter: &'a FileFilter,
+    base_path: PathBuf,
+    inner: walkdir::IntoIter,
+    // The .giantignore of each directory we're currently inside, with the depth of that directory
+    ignore_files: Vec<(usize, Gitignore)>,
+}
+
+impl Iterator for FilteredWalk<'_> {
+    type Item = WalkItem;
+
+    fn next(&mut self) -> Option<WalkItem> {
+        loop {
+            let entry = match self.inner.next()? {
+                Ok(entry) => entry,
+                Err(e) => {
+                    return Some(WalkItem::Error {
+                        path: e.path().map(|p| p.to_owned()).unwrap_or_default(),
+                        reason: e.to_string(),
+                    })
+                }
+            };
+
+            let depth = entry.depth();
+            // Leaving a directory means leaving its .giantignore behind
+            while self.ignore_files.last().is_some_and(|(d, _)| *d >= depth) {
+                self.ignore_files.pop();
+            }
+
+            let is_dir = entry.file_type().is_dir();
+            let enter_dir = |walk: &mut Self| {
+                if let Some(ignore_file) = walk.filter.load_ignore_file(entry.path()) {
+                    walk.ignore_files.push((depth, ignore_file));
+                }
+            };
+
+            if depth == 0 {
+                if is_dir {
+                    enter_dir(self);
+                }
+                continue;
+            }
+
+            if entry.path_is_symlink() {
+                continue;
+            }
+
+            let relative_path = entry
+                .path()
+                .strip_prefix(&self.base_path)
+                .unwrap_or(entry.path());
+            let exclusion = self.filter.check_entry(
+                self.ignore_files.iter().map(|(_, ignore_file)| ignore_file),
+                entry.path(),
+                relative_path,
+                is_dir,
+                || entry.metadata().ok().map(|m| m.len()),
+            );
+
+            if let Some(exclusion) = exclusion {
+                if is_dir {
+                    self.inner.skip_current_dir();
+                }
+                return Some(WalkItem::Excluded {
+                    path: entry.path().to_owned(),
+                    is_dir,
+                    exclusion,
+                });
+            }
+
+            if is_dir {
+                enter_dir(self);
+            } else {
+                return Some(WalkItem::File(WalkedFile {
+                    path: entry.into_path(),
+                    depth,
+                }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Mutex};
+
+    use super::*;
+
+    fn walk(
+        walker: Walker,
+        base: &Path,
+        options: FileFilterOptions,
+    ) -> (Vec<PathBuf>, Vec<(PathBuf, Exclusion)>) {
+        let filter = FileFilter::new(options).unwrap();
+        let files = Mutex::new(vec![]);
+        let excluded = Mutex::new(vec![]);
+        filter.walk_with(walker, 4, base, |item| {
+            match item {
+                WalkItem::File(file) => files
+                    .lock()
+                    .unwrap()
+                    .push(file.path.strip_prefix(base).unwrap().to_owned()),
+                WalkItem::Excluded {
+                    path, exclusion, ..
+                } => excluded
+                    .lock()
+                    .unwrap()
+                    .push((path.strip_prefix(base).unwrap().to_owned(), exclusion)),
+                WalkItem::Error { reason, .. } => panic!("{reason}"),
+            }
+            true
+        });
+
+        let mut files = files.into_inner().unwrap();
+        let mut excluded = excluded.into_inner().unwrap();
+        files.sort();
+        excluded.sort_by(|a, b| a.0.cmp(&b.0));
+        (files, excluded)
+    }
+
+    #[test]
+    fn applies_presets_globs_ignore_files_and_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_owned();
+        for (path, contents) in [
+            ("report.pdf", "pdf"),
+            ("notes.tmp", "tmp"),
+            (".DS_Store", "junk"),
+            ("~$report.docx", "lock"),
+            (".git/config", "git"),
+            ("emails/.giantignore", "*.log\n!keep.log\n"),
+            ("emails/debug.log", "log"),
+            ("emails/keep.log", "log"),
+            ("emails/inbox.eml", "a much larger email"),
+        ] {
+            let path = base.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        for walker in [Walker::Sequential, Walker::Parallel] {
+            let (files, excluded) = walk(
+                walker,
+                &base,
+                FileFilterOptions {
+                    exclude: vec!["*.tmp".into()],
+                    presets: vec![
+                        ExcludePreset::OsJunk,
+                        ExcludePreset::Vcs,
+                        ExcludePreset::OfficeLocks,
+                    ],
+                    max_size: Some(10),
+                    ..FileFilterOptions::default()
+                },
+            );
+
+            assert_eq!(
+                files,
+                vec![
+                    PathBuf::from("emails/keep.log"),
+                    PathBuf::from("report.pdf")
+                ]
+            );
+            let mut expected_excluded = vec![
+                (
+                    PathBuf::from(".DS_Store"),
+                    Exclusion::Preset(ExcludePreset::OsJunk),
+                ),
+                (PathBuf::from(".git"), Exclusion::Preset(ExcludePreset::Vcs)),
+                (
+                    PathBuf::from("emails/.giantignore"),
+                    Exclusion::IgnoreFile(base.join("emails/.giantignore")),
+                ),
+                (PathBuf::from("emails/inbox.eml"), Exclusion::TooLarge(10)),
+                (
+                    PathBuf::from("notes.tmp"),
+                    Exclusion::Pattern("*.tmp".into()),
+                ),
+                (
+                    PathBuf::from("~$report.docx"),
+                    Exclusion::Preset(ExcludePreset::OfficeLocks),
+                ),
+            ];
+            // The parallel walker leaves out what .giantignore files match without saying
+            if walker == Walker::Sequential {
+                expected_excluded.insert(
+                    3,
+                    (
+                        PathBuf::from("emails/debug.log"),
+                        Exclusion::IgnoreFile(base.join("emails/.giantignore")),
+                    ),
+                );
+            }
+            assert_eq!(excluded, expected_excluded);
+
+            let (files, _) = walk(
+                walker,
+                &base,
+                FileFilterOptions {
+                    include: vec!["*.pdf".into(), "*.eml".into()],
+                    ..FileFilterOptions::default()
+                },
+            );
+            assert_eq!(
+                files,
+                vec![
+                    PathBuf::from("emails/inbox.eml"),
+                    PathBuf::from("report.pdf")
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc::UnboundedReceiver,
+    task::JoinHandle,
+};
+
+use crate::model::{
+    cli_error::CliError,
+    cli_output::OutputFormat,
+    journal_header::{JournalHeader, JOURNAL_VERSION},
+    log_message::LogMessage,
+    uri::Uri,
+};
+
+pub enum LogDestination {
+    /// A new log named after the ingestion and the time, in this directory
+    Dir(PathBuf),
+    /// This exact file, appended to if it already exists
+    File(PathBuf),
+}
+
+pub struct IngestionLog {
+    pub path: PathBuf,
+    /// Whether we're adding to a log from a previous run
+    pub appending: bool,
+    writer: BufWriter<tokio::fs::File>,
+    format: OutputFormat,
+}
+
+impl IngestionLog {
+    /// Open the log, writing the header if it's new. An existing log is only appended to
+    /// if it was written by this journal version for the same ingestion.
+    pub async fn open(
+        destination: LogDestination,
+        ingestion_uri: &Uri,
+        header: &JournalHeader,
+        format: &OutputFormat,
+    ) -> Result<Self, CliError> {
+        let path = match destination {
+            LogDestination::Dir(dir) => dir.join(log_name(ingestion_uri, format, Utc::now())),
+            LogDestination::File(path) => {
+                if path.extension().and_then(|e| e.to_str()) != Some(format.to_extension()) {
+                    return Err(CliError::InputError(format!(
+                        "Log file '{}' must end in .{} to match the output format, or it can't be resumed from",
+                        path.display(),
+                        format.to_extension()
+                    )));
+                }
+                path
+            }
+        };
+
+        let appending = path.metadata().map(|m| m.len() > 0).unwrap_or(false);
+        if appending {
+            check_appendable(&path, header, format)?;
+        }
+
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| CliError::LogFile(path.clone(), e))?;
+        let mut writer = BufWriter::new(file);
+
+        if !appending {
+            let header = match format {
+                OutputFormat::Json => header.to_json(),
+                OutputFormat::Tsv => header.to_tsv_row(),
+            };
+            writer
+                .write_all(header.as_bytes())
+                .await
+                .map_err(|e| CliError::LogFile(path.clone(), e))?;
+        }
+
+        Ok(IngestionLog {
+            path,
+            appending,
+            writer,
+            format: format.clone(),
+        })
+    }
+
+    pub fn is_same_file(&self, other: &Path) -> bool {
+        match (self.path.canonicalize(), other.canonicalize()) {
+            (Ok(path), Ok(other)) => path == other,
+            _ => false,
+        }
+    }
+
+    /// Write every message sent until all the senders are dropped, then flush
+    pub fn spawn_writer(
+        mut self,
+        mut receiver: UnboundedReceiver<LogMessage>,
+    ) -> JoinHandle<Result<(), CliError>> {
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let line = match self.format {
+                    OutputFormat::Json => message.to_json(),
+                    OutputFormat::Tsv => message.to_tsv_row(),
+                };
+                self.writer
+                    .write_all(line.as_bytes())
+                    .await
+                    .map_err(|e| CliError::LogFile(self.path.clone(), e))?;
+            }
+
+            // Everything's been sent, make sure it reaches the disk
+            self.writer
+                .flush()
+                .await
+                .map_err(|e| CliError::LogFile(self.path.clone(), e))
+        })
+    }
+}
+
+fn check_appendable(
+    path: &Path,
+    expected: &JournalHeader,
+    format: &OutputFormat,
+) -> Result<(), CliError> {
+    let file = File::open(path).map_err(|e| CliError::LogFile(path.to_owned(), e))?;
+    let first_line = BufReader::new(file)
+        .lines()
+        .next()
+        .transpose()
+        .map_err(|e| CliError::LogFile(path.to_owned(), e))?
+        .unwrap_or_default();
+
+    let header = match format {
+        OutputFormat::Json => JournalHeader::from_json(&first_line),
+        OutputFormat::Tsv => JournalHeader::from_tsv_row(&first_line),
+    };
+
+    match header {
+        Some(header) if header.journal_version == JOURNAL_VERSION => {
+            header.check_resumable(expected)
+        }
+        Some(header) => Err(CliError::JournalMismatch(format!(
+            "can't append to a journal version {} log, this version writes {}. Use a new log file",
+            header.journal_version, JOURNAL_VERSION
+        ))),
+        None => Err(CliError::JournalMismatch(format!(
+            "'{}' isn't a giant-utils log, so won't be appended to",
+            path.display()
+        ))),
+    }
+}
+
+/// e.g. 2024-03-01T120000Z_my-collection_leak_ingestion.tsv
+/// Avoids colons and anything else some filesystems won't accept.
+fn log_name(ingestion_uri: &Uri, format: &OutputFormat, time: chrono::DateTime<Utc>) -> String {
+    let uri: String = ingestion_uri
+        .as_str()
+        .chars()
+        .map(|c| match c {
+            '/' => '_',
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '.' => c,
+            _ => '-',
+        })
+        .collect();
+
+    format!(
+        "{}_{}_ingestion.{}",
+        time.format("%Y-%m-%dT%H%M%SZ"),
+        uri,
+        format.to_extension()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn log_name_is_filesystem_safe() {
+        let time = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        assert_eq!(
+            log_name(&Uri::from("My Leak/drive: 1"), &OutputFormat::Tsv, time),
+            "2024-03-01T120000Z_My-Leak_drive--1_ingestion.tsv"
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_only_to_logs_for_the_same_ingestion() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ingestion.ndjson");
+        let uri = Uri::from("collection/ingestion");
+        let header = JournalHeader::new(&uri, dir.path()).unwrap();
+
+        let log = IngestionLog::open(
+            LogDestination::File(path.clone()),
+            &uri,
+            &header,
+            &OutputFormat::Json,
+        )
+        .await
+        .unwrap();
+        assert!(!log.appending);
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        drop(sender);
+        log.spawn_writer(receiver).await.unwrap().unwrap();
+
+        let reopened = IngestionLog::open(
+            LogDestination::File(path.clone()),
+            &uri,
+            &header,
+            &OutputFormat::Json,
+        )
+        .await
+        .unwrap();
+        assert!(reopened.appending);
+
+        let other_uri = Uri::from("collection/other");
+        let other = JournalHeader::new(&other_uri, dir.path()).unwrap();
+        let result = IngestionLog::open(
+            LogDestination::File(path.clone()),
+            &other_uri,
+            &other,
+            &OutputFormat::Json,
+        )
+        .await;
+        assert!(matches!(result, Err(CliError::JournalMismatch(_))));
+    }
+}
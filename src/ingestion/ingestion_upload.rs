@@ -1,196 +1,559 @@
 use std::{
-    path::Path,
-    time::{SystemTime, UNIX_EPOCH},
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use chrono::Utc;
-use futures::{stream, StreamExt};
+use futures::{future, stream, StreamExt};
 use humantime::format_duration;
-use indicatif::ProgressBar;
-use tokio::{
-    io::{AsyncWriteExt, BufWriter},
-    sync::mpsc,
-};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use tokio::sync::mpsc;
 use uuid::Uuid;
-use walkdir::WalkDir;
 
 use crate::{
-    ingestion::progress_reader::ProgressReader,
+    hash::{hash_path, hash_path_with},
+    ingestion::{
+        file_filter::{FileFilter, WalkItem, Walker},
+        ingestion_log::IngestionLog,
+        progress_reader::{Progress, ProgressReader},
+        shutdown::{resume_command, Shutdown},
+    },
     model::{
+        checksum::{FileChecksums, PartChecksums, UploadChecksum},
         cli_error::CliError,
-        cli_output::OutputFormat,
         file_metadata::FileMetadata,
         ingestion_file::IngestionFile,
+        ingestion_summary::IngestionSummary,
+        journal_header::JournalHeader,
         lang::Language,
         log_message::{FailureStage, LogMessage},
         uri::Uri,
     },
-    services::s3_client::S3Client,
+    services::{
+        giant_api::GiantApiClient,
+        retry::RetryPolicy,
+        s3_client::{is_transient_error, S3Client},
+    },
 };
 
+pub struct IngestionOptions {
+    pub num_parallel_uploads: usize,
+    /// Applied separately to each stage of each file's upload
+    pub upload_retry_policy: RetryPolicy,
+    /// If set, files whose hash this Giant already has are not uploaded again
+    pub skip_existing: Option<GiantApiClient>,
+    /// Leave the parts of failed multipart uploads in S3, to be picked up on resume
+    pub keep_incomplete_uploads: bool,
+    pub filter: FileFilter,
+    /// Write a log entry for every excluded file or directory
+    pub log_excluded: bool,
+    pub walker: Walker,
+    /// Only used by the parallel walker
+    pub walker_threads: usize,
+    /// How long uploads in progress get to finish after Ctrl-C or SIGTERM
+    pub shutdown_timeout: Duration,
+    /// Off when appending to the log we're resuming from, which already records them
+    pub log_already_processed: bool,
+    /// The header the log was opened with, which has the ingestion's absolute base path
+    pub journal_header: JournalHeader,
+}
+
+/// Files found by the walk but not yet picked up for upload
+const WALK_QUEUE_SIZE: usize = 100_000;
+
+/// How a file that got through all of its upload stages got there
+enum StagesOutcome {
+    /// Giant already had it
+    Skipped,
+    /// Missing the checksum if the data was uploaded by a previous run
+    Uploaded { checksum: Option<UploadChecksum> },
+}
+
+enum FileOutcome {
+    Uploaded { size: u64 },
+    AlreadyProcessed,
+    SkippedExisting { size: u64 },
+}
+
 pub async fn ingestion_upload(
     ingestion_uri: Uri,
     languages: &Vec<Language>,
     path: impl AsRef<Path>,
     s3_client: S3Client,
     progress_reader: ProgressReader,
-    format: &OutputFormat,
-    num_parallel_uploads: usize,
-) -> Result<(), CliError> {
-    let (sender, mut receiver) = mpsc::unbounded_channel::<LogMessage>();
-
-    // Slightly annoying clone so we can move the format into the background worker
-    let format = format.clone();
-    tokio::spawn(async move {
-        let log_name = format!(
-            "{}_ingestion.{}",
-            Utc::now().to_rfc3339(),
-            format.to_extension()
-        );
+    log: IngestionLog,
+    options: IngestionOptions,
+) -> Result<IngestionSummary, CliError> {
+    let num_parallel_uploads = options.num_parallel_uploads;
+    // Logged paths are absolute, so that they identify the file regardless of where we ran from
+    let base_path = options.journal_header.base_path.clone();
 
-        let log_file = tokio::fs::File::create(log_name)
-            .await
-            .expect("Failed to create log file");
-        let mut writer = BufWriter::new(log_file);
+    let (sender, receiver) = mpsc::unbounded_channel::<LogMessage>();
+    let log_path = log.path.clone();
+    let log_writer = log.spawn_writer(receiver);
+    let shutdown = Shutdown::listen(options.shutdown_timeout);
 
-        match format {
-            OutputFormat::Json => {
-                while let Some(message) = receiver.recv().await {
-                    let buf = message.to_json();
-                    writer.write_all(buf.as_bytes()).await.unwrap();
-                }
-            }
-            OutputFormat::Tsv => {
-                while let Some(message) = receiver.recv().await {
-                    let buf = message.to_tsv_row();
-                    writer.write_all(buf.as_bytes()).await.unwrap()
-                }
+    // A single walk feeds both the progress bar's total and the uploads. The queue is bounded,
+    // but long enough that the count usually gets well ahead of the uploads.
+    let pb = ProgressBar::new(0)
+        .with_style(ProgressStyle::with_template("{wide_bar} {pos}/{len} {msg}").unwrap());
+    pb.set_message("(still counting)");
+    if let Some(giant_client) = &options.skip_existing {
+        giant_client.set_progress_bar(&pb);
+    }
+
+    let (walk_sender, walk_receiver) = mpsc::channel::<WalkItem>(WALK_QUEUE_SIZE);
+    let walk_path = path.as_ref().to_owned();
+    let filter = options.filter;
+    let (walker, walker_threads) = (options.walker, options.walker_threads);
+    let walk_pb = pb.clone();
+    let walk = tokio::task::spawn_blocking(move || {
+        filter.walk_with(walker, walker_threads, &walk_path, |item| {
+            if matches!(item, WalkItem::File(_)) {
+                walk_pb.inc_length(1);
             }
-        }
+            // Only fails once the uploads have stopped listening
+            walk_sender.blocking_send(item).is_ok()
+        });
+        walk_pb.set_message("");
     });
 
-    println!("Counting files");
-    // Not ideal to traverse twice but at least this way we are able to measure progress
-    // Could experiment with spinning up two threads, one doing total counts and one doing uploads
-    // This could potentially cause thrashing on a spinning magnet.
-    let total_files = WalkDir::new(&path)
-        .into_iter()
-        .filter_map(|f| f.ok())
-        .filter(|f| !f.path_is_symlink() && !f.file_type().is_dir())
-        .count() as u64;
-
-    // Do it again, this time logging failures to read files
-    println!("Processing files");
-    let walker = WalkDir::new(&path)
-        .into_iter()
-        .filter_map(|f| {
-            if f.is_err() {
-                // log
+    let mut excluded_files = 0;
+    let mut excluded_dirs = 0;
+    let mut unreadable = 0;
+    let walked = stream::unfold(walk_receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    });
+    let files = walked.filter_map(|item| {
+        let file = match item {
+            WalkItem::File(file) => Some(file),
+            WalkItem::Excluded {
+                path: excluded_path,
+                is_dir,
+                exclusion,
+            } => {
+                if is_dir {
+                    excluded_dirs += 1;
+                } else {
+                    excluded_files += 1;
+                }
+                if options.log_excluded {
+                    let relative_path = excluded_path
+                        .strip_prefix(&path)
+                        .unwrap_or(&excluded_path)
+                        .to_owned();
+                    // Only fails if the log writer has gone, which the uploads will report
+                    let _ = sender.send(LogMessage::Excluded {
+                        path: base_path.join(&relative_path),
+                        relative_path,
+                        is_dir,
+                        reason: exclusion.to_string(),
+                    });
+                }
+                None
             }
-            f.ok()
-        })
-        .filter(|f| !f.path_is_symlink() && !f.file_type().is_dir());
-
-    let pb = ProgressBar::new(total_files);
+            WalkItem::Error {
+                path: unreadable_path,
+                reason,
+            } => {
+                eprintln!(
+                    "Skipping unreadable {}: {reason}",
+                    unreadable_path.display()
+                );
+                unreadable += 1;
+                let relative_path = unreadable_path
+                    .strip_prefix(&path)
+                    .unwrap_or(&unreadable_path)
+                    .to_owned();
+                let _ = sender.send(LogMessage::WalkFailure {
+                    path: base_path.join(&relative_path),
+                    relative_path,
+                    reason,
+                });
+                None
+            }
+        };
+        future::ready(file)
+    });
+    // Stop picking up new files once asked to, the uploads already started carry on
+    let files = files.take_until(shutdown.stopping());
 
-    println!("Starting ingestion with buffer size {num_parallel_uploads}");
+    eprintln!("Starting ingestion with buffer size {num_parallel_uploads}");
     let start_time = SystemTime::now();
-    let results = stream::iter(walker)
-        .map(|dir| {
+    let results = files
+        .map(|file| {
             let pb = &pb;
             let ingestion_uri = &ingestion_uri;
             let path = &path;
             let languages = &languages;
             let s3_client = &s3_client;
+            let base_path = &base_path;
+            let retry_policy = &options.upload_retry_policy;
+            let skip_existing = options.skip_existing.as_ref();
+            let keep_incomplete_uploads = options.keep_incomplete_uploads;
+            let log_already_processed = options.log_already_processed;
             let log_sender = sender.clone();
+            let shutdown = &shutdown;
             let progress_guard = progress_reader.guard();
 
             async move {
                 let start = SystemTime::now();
-                let start_millis = start.duration_since(UNIX_EPOCH).unwrap().as_millis();
-                let file_size = dir.metadata()?.len();
-
-                if progress_guard.contains_key(dir.path()) {
-                    // The file has already been processed, skip over it
-                    log_sender.send(LogMessage::Success {
-                        path: path.as_ref().to_owned(),
-                        size: file_size,
-                        start_millis,
-                        end_millis: start.duration_since(UNIX_EPOCH).unwrap().as_millis(),
-                    })?;
-                    pb.inc(1);
-                    Ok(())
-                } else {
-                    let uuid = Uuid::new_v4();
+                let start_millis = start.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+                let stripped = file.path.strip_prefix(path);
+                // The walk only finds files under the base path, if not the full path is logged
+                let relative_path = stripped.clone().unwrap_or(&file.path).to_owned();
+                let absolute_path = base_path.join(&relative_path);
+                let uri = ingestion_uri.extend_from_path(&relative_path);
+
+                let file_size = stripped
+                    .map_err(anyhow::Error::from)
+                    .and_then(|_| Ok(file.path.metadata()?.len()));
+                let file_size = match file_size {
+                    Ok(file_size) => file_size,
+                    Err(e) => {
+                        // Nothing has been read or uploaded, so like a failed hash
+                        // no keys are picked up for it on resume
+                        eprintln!("Failure in ingestion pipeline: {e}");
+                        pb.inc(1);
+                        log_sender.send(LogMessage::Failure {
+                            path: absolute_path,
+                            relative_path,
+                            uri,
+                            hash: None,
+                            data_key: String::new(),
+                            metadata_key: String::new(),
+                            size: 0,
+                            start_millis,
+                            end_millis: now_millis(),
+                            attempts: 0,
+                            failure_stage: FailureStage::Hash,
+                            reason: e.to_string(),
+                        })?;
+                        return Err(e);
+                    }
+                };
+
+                // Keys from a previous run which got part of the way through uploading this file
+                let (pending_keys, data_uploaded) =
+                    match progress_guard.get(&absolute_path) {
+                        Some(Progress::Done) => {
+                            // The file has already been processed, skip over it
+                            if log_already_processed {
+                                log_sender.send(LogMessage::AlreadyProcessed {
+                                    path: absolute_path,
+                                    relative_path,
+                                    uri,
+                                    size: file_size,
+                                })?;
+                            }
+                            pb.inc(1);
+                            return Ok(FileOutcome::AlreadyProcessed);
+                        }
+                        Some(Progress::DataPending {
+                            data_key,
+                            metadata_key,
+                        }) => (Some((data_key.clone(), metadata_key.clone())), false),
+                        Some(Progress::MetadataPending {
+                            data_key,
+                            metadata_key,
+                        }) => (Some((data_key.clone(), metadata_key.clone())), true),
+                        None => (None, false),
+                    };
 
-                    const DATA_PREFIX: &str = "data";
-                    const METADATA_PREFIX: &str = "metadata";
-                    const DATA_SUFFIX: &str = "data";
-                    const METADATA_SUFFIX: &str = "metadata.json";
+                let uuid = Uuid::new_v4();
 
-                    let file_size = dir.metadata()?.len();
-                    let ingestion_file =
-                        IngestionFile::from_file(ingestion_uri, path, &dir).unwrap();
+                const DATA_PREFIX: &str = "data";
+                const METADATA_PREFIX: &str = "metadata";
+                const DATA_SUFFIX: &str = "data";
+                const METADATA_SUFFIX: &str = "metadata.json";
+
+                let (data_key, metadata_key) = pending_keys.unwrap_or_else(|| {
+                    (
+                        format!("{DATA_PREFIX}/{start_millis}_{uuid}.{DATA_SUFFIX}"),
+                        format!("{METADATA_PREFIX}/{start_millis}_{uuid}.{METADATA_SUFFIX}"),
+                    )
+                });
+
+                // Kept outside the stages so failure logs can include it if we got that far
+                let mut hash = None;
+                let mut attempts = 0;
+                let result = async {
+                    // Like hashing, this reads from the file without uploading anything
+                    let ingestion_file = IngestionFile::from_file(ingestion_uri, path, &file.path)
+                        .map_err(|e| (FailureStage::Hash, e))?;
                     let metadata = FileMetadata::new(ingestion_uri, ingestion_file, languages);
-                    let metadata_key =
-                        format!("{METADATA_PREFIX}/{start_millis}_{uuid}.{METADATA_SUFFIX}");
-                    if let Err(e) = s3_client.upload_metadata(&metadata_key, metadata).await {
+
+                    // Reading a file once for both is much quicker from slow disks
+                    let part_checksums = s3_client
+                        .part_checksums(file_size)
+                        .map_err(|e| (FailureStage::UploadData, e))?;
+                    let (file_hash, checksums) = shutdown
+                        .unless_aborted(hash_and_checksum_in_background(
+                            &file.path,
+                            part_checksums,
+                        ))
+                        .await
+                        .map_err(|e| (FailureStage::Hash, e))?;
+                    hash = Some(file_hash.clone());
+
+                    if let Some(giant_client) = skip_existing {
+                        match giant_client.check_hash_exists(&file_hash).await {
+                            Ok(true) => return Ok((file_hash, StagesOutcome::Skipped)),
+                            Ok(false) => {}
+                            // Giant dedupes blobs itself, so uploading anyway only costs bandwidth
+                            Err(e) => eprintln!(
+                                "Couldn't check whether {} is already in Giant, uploading anyway: {e}",
+                                file.path.display()
+                            ),
+                        }
+                    }
+
+                    // The data goes first, so that Giant never sees metadata for data that
+                    // isn't there. Each stage is retried on its own, and a failed stage is
+                    // picked up from the same keys on resume.
+                    let checksum = if data_uploaded {
+                        // Verified by the run which uploaded it
+                        None
+                    } else {
+                        let checksum = shutdown
+                            .unless_aborted(with_retries(
+                                retry_policy,
+                                &mut attempts,
+                                &file.path,
+                                || s3_client.upload_file(&data_key, &file.path, &checksums),
+                            ))
+                            .await
+                            .map_err(|e| (FailureStage::UploadData, e))?;
+                        Some(checksum)
+                    };
+
+                    shutdown
+                        .unless_aborted(with_retries(
+                            retry_policy,
+                            &mut attempts,
+                            &file.path,
+                            || s3_client.upload_metadata(&metadata_key, &metadata),
+                        ))
+                        .await
+                        .map_err(|e| (FailureStage::UploadMetadata, e))?;
+
+                    Ok((file_hash, StagesOutcome::Uploaded { checksum }))
+                }
+                .await;
+                pb.inc(1);
+
+                match result {
+                    Ok((hash, StagesOutcome::Skipped)) => {
+                        log_sender.send(LogMessage::SkippedExisting {
+                            path: absolute_path,
+                            relative_path,
+                            uri,
+                            hash,
+                            size: file_size,
+                            start_millis,
+                            end_millis: now_millis(),
+                        })?;
+                        Ok(FileOutcome::SkippedExisting { size: file_size })
+                    }
+                    Ok((hash, StagesOutcome::Uploaded { checksum })) => {
+                        log_sender.send(LogMessage::Success {
+                            path: absolute_path,
+                            relative_path,
+                            uri,
+                            hash,
+                            data_key,
+                            metadata_key,
+                            size: file_size,
+                            start_millis,
+                            end_millis: now_millis(),
+                            attempts,
+                            checksum,
+                        })?;
+                        Ok(FileOutcome::Uploaded { size: file_size })
+                    }
+                    Err((failure_stage, e)) => {
                         eprintln!("Failure in ingestion pipeline: {e}");
+                        if matches!(failure_stage, FailureStage::UploadData)
+                            && !keep_incomplete_uploads
+                        {
+                            if let Err(abort_error) =
+                                s3_client.abort_incomplete_upload(&data_key).await
+                            {
+                                eprintln!(
+                                    "Failed to clean up incomplete upload of {}: {abort_error}",
+                                    file.path.display()
+                                );
+                            }
+                        }
                         log_sender.send(LogMessage::Failure {
-                            path: path.as_ref().to_owned(),
+                            path: absolute_path,
+                            relative_path,
+                            uri,
+                            hash,
+                            data_key,
+                            metadata_key,
                             size: file_size,
                             start_millis,
-                            end_millis: start.duration_since(UNIX_EPOCH).unwrap().as_millis(),
-                            failure_stage: FailureStage::UploadMetadata,
+                            end_millis: now_millis(),
+                            attempts,
+                            failure_stage,
                             reason: e.to_string(),
                         })?;
-                        pb.inc(1);
-                        Err(e)?
-                    } else {
-                        let data_key = format!("{DATA_PREFIX}/{start_millis}_{uuid}.{DATA_SUFFIX}");
-                        if let Err(e) = s3_client.upload_file(&data_key, &dir.path()).await {
-                            eprintln!("Failure in ingestion pipeline: {e}");
-                            log_sender.send(LogMessage::Failure {
-                                path: path.as_ref().to_owned(),
-                                size: file_size,
-                                start_millis,
-                                end_millis: start.duration_since(UNIX_EPOCH).unwrap().as_millis(),
-                                failure_stage: FailureStage::UploadData,
-                                reason: e.to_string(),
-                            })?;
-                            pb.inc(1);
-                            Err(e)?
-                        } else {
-                            log_sender.send(LogMessage::Success {
-                                path: path.as_ref().to_owned(),
-                                size: file_size,
-                                start_millis,
-                                end_millis: start.duration_since(UNIX_EPOCH).unwrap().as_millis(),
-                            })?;
-                            pb.inc(1);
-                            Ok(())
-                        }
+                        Err(e)
                     }
                 }
             }
         })
         .buffer_unordered(num_parallel_uploads)
-        .collect::<Vec<anyhow::Result<()>>>()
+        .collect::<Vec<anyhow::Result<FileOutcome>>>()
         .await;
 
-    let success_count = results
-        .iter()
-        .filter(|status| matches!(status, Ok(())))
-        .count();
-    let failure_count = results.len() - success_count;
+    // Whatever the walk hadn't reached yet will be walked again on resume
+    if let Err(e) = walk.await {
+        eprintln!("Directory walk stopped early: {e}");
+        unreadable += 1;
+        let _ = sender.send(LogMessage::WalkFailure {
+            path: base_path.clone(),
+            relative_path: PathBuf::new(),
+            reason: format!("Directory walk stopped early: {e}"),
+        });
+    }
 
-    println!("Finished!");
-    println!(
-        "  Elapsed: {}",
-        format_duration(start_time.elapsed().unwrap())
+    let mut summary = IngestionSummary {
+        excluded_files,
+        excluded_dirs,
+        unreadable,
+        ..IngestionSummary::default()
+    };
+    for result in &results {
+        match result {
+            Ok(FileOutcome::Uploaded { size }) => {
+                summary.success += 1;
+                summary.bytes_uploaded += size;
+            }
+            Ok(FileOutcome::AlreadyProcessed) => summary.already_processed += 1,
+            Ok(FileOutcome::SkippedExisting { size }) => {
+                summary.skipped_existing += 1;
+                summary.bytes_skipped_existing += size;
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(CliError::Interrupted)) => {
+                summary.interrupted += 1
+            }
+            Err(_) => summary.failure += 1,
+        }
+    }
+
+    let elapsed = start_time.elapsed().unwrap_or_default();
+    summary.elapsed_secs = elapsed.as_secs_f64();
+    if summary.elapsed_secs > 0.0 {
+        summary.bytes_per_sec = summary.bytes_uploaded as f64 / summary.elapsed_secs;
+    }
+
+    eprintln!("Finished!");
+    eprintln!("  Elapsed: {}", format_duration(elapsed));
+    eprintln!(
+        "  Success: {}, {} at {}/s",
+        summary.success,
+        HumanBytes(summary.bytes_uploaded),
+        HumanBytes(summary.bytes_per_sec as u64)
     );
-    println!("  Success: {success_count}");
-    println!("  Failure: {failure_count}");
+    eprintln!("  Failure: {}", summary.failure);
+    if summary.interrupted > 0 {
+        eprintln!("  Interrupted: {}", summary.interrupted);
+    }
+    if unreadable > 0 {
+        eprintln!("  Unreadable: {unreadable}");
+    }
+    if excluded_files > 0 || excluded_dirs > 0 {
+        eprintln!("  Excluded: {excluded_files} files, {excluded_dirs} directories");
+    }
+    if summary.already_processed > 0 {
+        eprintln!("  Already processed: {}", summary.already_processed);
+    }
+    if options.skip_existing.is_some() {
+        eprintln!(
+            "  Skipped (already in Giant): {}, saving {}",
+            summary.skipped_existing,
+            HumanBytes(summary.bytes_skipped_existing)
+        );
+    }
+
+    // Every upload has finished with its sender, so this waits for the last lines to be written
+    drop(sender);
+    match log_writer.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("{e}"),
+        Err(e) => eprintln!("Failed to write the log: {e}"),
+    }
+
+    let log_path = std::env::current_dir()
+        .map(|dir| dir.join(&log_path))
+        .unwrap_or(log_path);
+    summary.log_path = log_path.display().to_string();
+    summary.stopped_early = shutdown.is_stopping();
+
+    if summary.failure_exit_code().is_some() {
+        eprintln!("To retry failures and pick up where this left off, run:");
+        eprintln!("  {}", resume_command(&log_path));
+    }
 
-    Ok(())
+    Ok(summary)
+}
+
+pub async fn hash_in_background(path: &Path) -> anyhow::Result<String> {
+    let path = path.to_owned();
+    let hash = tokio::task::spawn_blocking(move || hash_path(path)).await??;
+    Ok(hash)
+}
+
+/// Hash a file for Giant, computing the checksums S3 will verify its upload against in the same read
+async fn hash_and_checksum_in_background(
+    path: &Path,
+    mut checksums: PartChecksums,
+) -> anyhow::Result<(String, FileChecksums)> {
+    let path = path.to_owned();
+    let hash = tokio::task::spawn_blocking(move || {
+        let hash = hash_path_with(path, |chunk| checksums.update(chunk))?;
+        Ok::<_, CliError>((hash, checksums.finish()))
+    })
+    .await??;
+    Ok(hash)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Run an upload, retrying it with backoff for as long as it fails with transient S3 errors.
+/// `attempts` is incremented for every request made, so it can be reported in the log.
+async fn with_retries<T, F, Fut>(
+    retry_policy: &RetryPolicy,
+    attempts: &mut u32,
+    path: &Path,
+    mut upload: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut stage_attempt = 1;
+    loop {
+        *attempts += 1;
+        match upload().await {
+            Err(e) if stage_attempt < retry_policy.max_attempts && is_transient_error(&e) => {
+                let delay = retry_policy.backoff(stage_attempt);
+                eprintln!(
+                    "Transient failure uploading {}, retrying in {}: {e}",
+                    path.display(),
+                    format_duration(delay)
+                );
+                tokio::time::sleep(delay).await;
+                stage_attempt += 1;
+            }
+            result => return result,
+        }
+    }
 }
@@ -1,2 +1,6 @@
+pub mod dry_run;
+pub mod file_filter;
+pub mod ingestion_log;
 pub mod ingestion_upload;
 pub mod progress_reader;
+pub mod shutdown;
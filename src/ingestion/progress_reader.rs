@@ -6,55 +6,396 @@ use std::{
 
 use flashmap::{self, ReadHandle};
 
-use crate::model::{cli_error::CliError, log_message::LogMessage};
+use crate::model::{
+    cli_error::CliError,
+    journal_header::JournalHeader,
+    log_message::{FailureStage, LogMessage},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// Uploaded or deliberately skipped by a previous run
+    Done,
+    /// A previous run failed to upload the data, which is retried against the
+    /// same key so that any parts it got into S3 can be reused
+    DataPending {
+        data_key: String,
+        metadata_key: String,
+    },
+    /// A previous run uploaded the data but not the metadata which refers to it
+    MetadataPending {
+        data_key: String,
+        metadata_key: String,
+    },
+}
 
-pub type ProgressReader = ReadHandle<PathBuf, bool>;
+pub type ProgressReader = ReadHandle<PathBuf, Progress>;
 
 pub fn empty_progress_reader() -> ProgressReader {
-    let (_, read) = flashmap::new::<PathBuf, bool>();
+    let (_, read) = flashmap::new::<PathBuf, Progress>();
     read
 }
 
-pub fn progress_reader_from_path(path: impl AsRef<Path>) -> Result<ProgressReader, CliError> {
-    let (mut write, read) = flashmap::new::<PathBuf, bool>();
+/// Build the progress of each file in a previous run, keyed by absolute path.
+/// Only files that were uploaded or deliberately skipped count as done,
+/// so failed files are retried on resume.
+pub fn progress_reader_from_path(
+    path: impl AsRef<Path>,
+    expected_header: &JournalHeader,
+) -> Result<ProgressReader, CliError> {
+    let (mut write, read) = flashmap::new::<PathBuf, Progress>();
 
     let mut write_guard = write.guard();
     match path.as_ref().extension().and_then(|e| e.to_str()) {
         Some("tsv") => {
-            let file = File::open(path)?;
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
+            let file = File::open(&path)?;
+            let mut lines = BufReader::new(file).lines().peekable();
+
+            // An empty log has nothing to resume
+            if let Some(Ok(first_line)) = lines.peek() {
+                match JournalHeader::from_tsv_row(first_line) {
+                    Some(header) => {
+                        header.check_resumable(expected_header)?;
+                        lines.next();
+                    }
+                    None => return Err(missing_header(&path)),
+                }
+            }
+
+            for line in lines {
                 let line = line?;
 
-                let mut cols = line.split('\t');
-                let status = cols
-                    .next()
-                    .ok_or_else(|| CliError::InputError("Invalid column in log file".into()))?;
-                let path = cols
-                    .next()
-                    .ok_or_else(|| CliError::InputError("Invalid column in log file".into()))?;
+                let cols: Vec<&str> = line.split('\t').collect();
+                if cols.len() < 2 {
+                    return Err(CliError::InputError("Invalid column in log file".into()));
+                }
+                let (status, path) = (cols[0], PathBuf::from(cols[1]));
 
-                if status == "success" {
-                    write_guard.insert(PathBuf::from(path), true);
+                match status {
+                    "success" | "skipped_existing" | "already_processed" => {
+                        write_guard.insert(path, Progress::Done);
+                    }
+                    "failure" if cols.len() >= 12 => {
+                        let failure_stage = match cols[11] {
+                            "failed_to_upload_data" => FailureStage::UploadData,
+                            "failed_to_upload_metadata" => FailureStage::UploadMetadata,
+                            _ => FailureStage::Hash,
+                        };
+                        let pending = pending_after_failure(
+                            &failure_stage,
+                            cols[5].to_owned(),
+                            cols[6].to_owned(),
+                        );
+                        if let Some(pending) = pending {
+                            write_guard.insert(path, pending);
+                        }
+                    }
+                    _ => {}
                 }
             }
         }
         Some("ndjson") => {
-            let file = File::open(path)?;
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
+            let file = File::open(&path)?;
+            let mut lines = BufReader::new(file).lines().peekable();
+
+            // An empty log has nothing to resume
+            if let Some(Ok(first_line)) = lines.peek() {
+                match JournalHeader::from_json(first_line) {
+                    Some(header) => {
+                        header.check_resumable(expected_header)?;
+                        lines.next();
+                    }
+                    None => return Err(missing_header(&path)),
+                }
+            }
+
+            for line in lines {
                 let line = line?;
                 let log_entry = serde_json::from_str(&line)?;
 
-                if let LogMessage::Failure { path, .. } = log_entry {
-                    write_guard.insert(path, true);
+                match log_entry {
+                    LogMessage::Success { path, .. }
+                    | LogMessage::SkippedExisting { path, .. }
+                    | LogMessage::AlreadyProcessed { path, .. } => {
+                        write_guard.insert(path, Progress::Done);
+                    }
+                    LogMessage::Failure {
+                        path,
+                        failure_stage,
+                        data_key,
+                        metadata_key,
+                        ..
+                    } => {
+                        let pending = pending_after_failure(&failure_stage, data_key, metadata_key);
+                        if let Some(pending) = pending {
+                            write_guard.insert(path, pending);
+                        }
+                    }
+                    LogMessage::Excluded { .. } | LogMessage::WalkFailure { .. } => {}
                 }
             }
         }
-        _ => {}
+        _ => {
+            return Err(CliError::InputError(format!(
+                "Progress log '{}' must be a .tsv or .ndjson file",
+                path.as_ref().display()
+            )))
+        }
     }
 
     write_guard.publish();
 
     Ok(read)
 }
+
+/// What a failed file from a previous run left in S3, if anything
+fn pending_after_failure(
+    failure_stage: &FailureStage,
+    data_key: String,
+    metadata_key: String,
+) -> Option<Progress> {
+    match failure_stage {
+        FailureStage::UploadData => Some(Progress::DataPending {
+            data_key,
+            metadata_key,
+        }),
+        FailureStage::UploadMetadata => Some(Progress::MetadataPending {
+            data_key,
+            metadata_key,
+        }),
+        // Nothing got as far as S3
+        _ => None,
+    }
+}
+
+/// Logs without a header were written before their lines recorded what is needed to resume
+fn missing_header(path: impl AsRef<Path>) -> CliError {
+    CliError::JournalMismatch(format!(
+        "'{}' has no journal header, so was written by a version of giant-utils too old to resume from",
+        path.as_ref().display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Write};
+
+    use tempfile::TempPath;
+
+    use super::*;
+    use crate::model::{
+        checksum::{ChecksumAlgorithm, UploadChecksum},
+        journal_header::JOURNAL_VERSION,
+        uri::Uri,
+    };
+
+    fn header(ingestion_uri: &str) -> JournalHeader {
+        JournalHeader::new(&Uri::from(ingestion_uri), env::temp_dir()).unwrap()
+    }
+
+    fn messages() -> Vec<LogMessage> {
+        vec![
+            LogMessage::Success {
+                path: PathBuf::from("/data/uploaded"),
+                relative_path: PathBuf::from("uploaded"),
+                uri: Uri::from("collection/ingestion/uploaded"),
+                hash: "hash".into(),
+                data_key: "data/1_uuid.data".into(),
+                metadata_key: "metadata/1_uuid.metadata.json".into(),
+                size: 10,
+                start_millis: 1,
+                end_millis: 2,
+                attempts: 1,
+                checksum: Some(UploadChecksum {
+                    algorithm: ChecksumAlgorithm::Sha256,
+                    checksum: "checksum".into(),
+                    e_tag: "\"e-tag\"".into(),
+                }),
+            },
+            LogMessage::Failure {
+                path: PathBuf::from("/data/failed"),
+                relative_path: PathBuf::from("failed"),
+                uri: Uri::from("collection/ingestion/failed"),
+                hash: None,
+                data_key: "data/3_uuid.data".into(),
+                metadata_key: "metadata/3_uuid.metadata.json".into(),
+                size: 20,
+                start_millis: 3,
+                end_millis: 4,
+                attempts: 3,
+                failure_stage: FailureStage::UploadData,
+                reason: "Timed\tout\nagain".into(),
+            },
+            LogMessage::Failure {
+                path: PathBuf::from("/data/unhashable"),
+                relative_path: PathBuf::from("unhashable"),
+                uri: Uri::from("collection/ingestion/unhashable"),
+                hash: None,
+                data_key: "data/4_uuid.data".into(),
+                metadata_key: "metadata/4_uuid.metadata.json".into(),
+                size: 50,
+                start_millis: 4,
+                end_millis: 5,
+                attempts: 0,
+                failure_stage: FailureStage::Hash,
+                reason: "Permission denied".into(),
+            },
+            LogMessage::Failure {
+                path: PathBuf::from("/data/unreferenced"),
+                relative_path: PathBuf::from("unreferenced"),
+                uri: Uri::from("collection/ingestion/unreferenced"),
+                hash: Some("unreferenced-hash".into()),
+                data_key: "data/5_uuid.data".into(),
+                metadata_key: "metadata/5_uuid.metadata.json".into(),
+                size: 60,
+                start_millis: 5,
+                end_millis: 6,
+                attempts: 4,
+                failure_stage: FailureStage::UploadMetadata,
+                reason: "Access denied".into(),
+            },
+            LogMessage::SkippedExisting {
+                path: PathBuf::from("/data/existing"),
+                relative_path: PathBuf::from("existing"),
+                uri: Uri::from("collection/ingestion/existing"),
+                hash: "existing-hash".into(),
+                size: 40,
+                start_millis: 5,
+                end_millis: 6,
+            },
+            LogMessage::AlreadyProcessed {
+                path: PathBuf::from("/data/resumed"),
+                relative_path: PathBuf::from("resumed"),
+                uri: Uri::from("collection/ingestion/resumed"),
+                size: 30,
+            },
+            LogMessage::WalkFailure {
+                path: PathBuf::from("/data/locked"),
+                relative_path: PathBuf::from("locked"),
+                reason: "Permission denied".into(),
+            },
+        ]
+    }
+
+    /// Deleted when dropped
+    fn write_log(extension: &str, header: Option<&JournalHeader>) -> TempPath {
+        let json = extension == "ndjson";
+        let mut contents = String::new();
+
+        if let Some(header) = header {
+            contents.push_str(&if json {
+                header.to_json()
+            } else {
+                header.to_tsv_row()
+            });
+        }
+        for message in messages() {
+            contents.push_str(&if json {
+                message.to_json()
+            } else {
+                message.to_tsv_row()
+            });
+        }
+
+        let mut file = tempfile::Builder::new()
+            .suffix(&format!("_ingestion.{extension}"))
+            .tempfile()
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file.into_temp_path()
+    }
+
+    fn assert_resumes_from_each_status(extension: &str) {
+        let header = header("collection/ingestion");
+        let log = write_log(extension, Some(&header));
+
+        let progress = progress_reader_from_path(&log, &header).unwrap();
+        let guard = progress.guard();
+        assert_eq!(
+            guard.get(Path::new("/data/uploaded")),
+            Some(&Progress::Done)
+        );
+        assert_eq!(
+            guard.get(Path::new("/data/existing")),
+            Some(&Progress::Done)
+        );
+        assert_eq!(guard.get(Path::new("/data/resumed")), Some(&Progress::Done));
+        assert_eq!(
+            guard.get(Path::new("/data/failed")),
+            Some(&Progress::DataPending {
+                data_key: "data/3_uuid.data".into(),
+                metadata_key: "metadata/3_uuid.metadata.json".into(),
+            })
+        );
+        assert_eq!(
+            guard.get(Path::new("/data/unreferenced")),
+            Some(&Progress::MetadataPending {
+                data_key: "data/5_uuid.data".into(),
+                metadata_key: "metadata/5_uuid.metadata.json".into(),
+            })
+        );
+        assert_eq!(guard.get(Path::new("/data/unhashable")), None);
+        assert_eq!(guard.get(Path::new("/data/locked")), None);
+    }
+
+    fn assert_rejects_other_ingestion(extension: &str) {
+        let log = write_log(extension, Some(&header("collection/ingestion")));
+
+        let result = progress_reader_from_path(&log, &header("collection/other"));
+        assert!(matches!(result, Err(CliError::JournalMismatch(_))));
+    }
+
+    #[test]
+    fn tsv_round_trip_resumes_from_each_status() {
+        assert_resumes_from_each_status("tsv");
+    }
+
+    #[test]
+    fn ndjson_round_trip_resumes_from_each_status() {
+        assert_resumes_from_each_status("ndjson");
+    }
+
+    #[test]
+    fn tsv_rejects_log_for_other_ingestion() {
+        assert_rejects_other_ingestion("tsv");
+    }
+
+    #[test]
+    fn ndjson_rejects_log_for_other_ingestion() {
+        assert_rejects_other_ingestion("ndjson");
+    }
+
+    #[test]
+    fn rejects_log_from_newer_journal_version() {
+        let expected = header("collection/ingestion");
+        let newer = JournalHeader {
+            journal_version: JOURNAL_VERSION + 1,
+            ..header("collection/ingestion")
+        };
+        let log = write_log("tsv", Some(&newer));
+
+        let result = progress_reader_from_path(&log, &expected);
+        assert!(matches!(result, Err(CliError::JournalMismatch(_))));
+    }
+
+    #[test]
+    fn rejects_log_without_header() {
+        let log = write_log("ndjson", None);
+
+        let result = progress_reader_from_path(&log, &header("collection/ingestion"));
+        assert!(matches!(result, Err(CliError::JournalMismatch(_))));
+    }
+
+    #[test]
+    fn header_survives_tsv_and_json() {
+        let original = header("collection/ingestion");
+        assert_eq!(
+            JournalHeader::from_tsv_row(&original.to_tsv_row()).as_ref(),
+            Some(&original)
+        );
+        assert_eq!(
+            JournalHeader::from_json(&original.to_json()).as_ref(),
+            Some(&original)
+        );
+    }
+}
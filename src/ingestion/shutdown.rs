@@ -0,0 +1,143 @@
+// Stops an ingestion cleanly on Ctrl-C or SIGTERM. The first signal stops new files being
+// started and gives those in flight a grace period to finish, after which (or on a second
+// signal) they're interrupted so that the log can be flushed and the run resumed.
+
+use std::{future::Future, path::Path, time::Duration};
+
+use humantime::format_duration;
+use tokio::sync::watch;
+
+use crate::model::cli_error::CliError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Running,
+    Stopping,
+    Aborting,
+}
+
+#[derive(Clone)]
+pub struct Shutdown {
+    state: watch::Receiver<State>,
+}
+
+impl Shutdown {
+    /// Start listening for signals in the background
+    pub fn listen(grace_period: Duration) -> Self {
+        let (sender, receiver) = watch::channel(State::Running);
+
+        tokio::spawn(async move {
+            if signalled().await.is_err() {
+                return;
+            }
+            eprintln!(
+                "Stopping: no new files will be started. Waiting up to {} for uploads in progress, signal again to interrupt them now",
+                format_duration(grace_period)
+            );
+            let _ = sender.send(State::Stopping);
+
+            tokio::select! {
+                _ = tokio::time::sleep(grace_period) => {}
+                _ = signalled() => {}
+            }
+            eprintln!("Interrupting uploads in progress");
+            let _ = sender.send(State::Aborting);
+        });
+
+        Shutdown { state: receiver }
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        *self.state.borrow() >= State::Stopping
+    }
+
+    /// Resolves once we've been asked to stop starting new files
+    pub async fn stopping(&self) {
+        self.reached(State::Stopping).await
+    }
+
+    /// Run some work, giving up with `CliError::Interrupted` if uploads in progress are interrupted
+    pub async fn unless_aborted<T>(
+        &self,
+        work: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        tokio::select! {
+            result = work => result,
+            _ = self.reached(State::Aborting) => Err(CliError::Interrupted.into()),
+        }
+    }
+
+    async fn reached(&self, state: State) {
+        let mut receiver = self.state.clone();
+        while *receiver.borrow() < state {
+            // The listener only goes away once it's aborting, or if it couldn't listen at all
+            if receiver.changed().await.is_err() {
+                if *receiver.borrow() < state {
+                    std::future::pending::<()>().await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn signalled() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn signalled() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// The command this process was run with, resuming from the given log
+pub fn resume_command(log_path: &Path) -> String {
+    let mut args = vec![];
+    let mut original_args = std::env::args();
+    while let Some(arg) = original_args.next() {
+        if arg == "--progress-from" || arg == "-p" {
+            original_args.next();
+        } else if !arg.starts_with("--progress-from=") {
+            args.push(arg);
+        }
+    }
+    args.push("--progress-from".to_owned());
+    args.push(log_path.display().to_string());
+
+    args.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c));
+    if safe {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_args_for_the_shell() {
+        assert_eq!(shell_quote("collection/ingestion"), "collection/ingestion");
+        assert_eq!(shell_quote("My Leak"), "'My Leak'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+}
@@ -1,103 +1,288 @@
-use std::path::PathBuf;
+use std::{env, ffi::OsString, io::Read, path::PathBuf, time::Duration};
 
 use crate::{
     giant_api::{GiantApiClient, ListBlobsFilter},
-    services::s3_client::S3Client,
+    services::{
+        retry::{parse_status_code, RetryPolicy},
+        s3_client::{MultipartConfig, S3Client, MAX_PART_SIZE},
+    },
 };
-use clap::{Parser, Subcommand};
-use hash::hash_file;
+use check_dir::check_dir;
+use chrono::{DateTime, Utc};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::{load_profile, parse_giant_uri, Profile};
+use futures::TryStreamExt;
+use hash::{hash_file, hash_paths};
 use ingestion::{
-    ingestion_upload::ingestion_upload,
+    file_filter::{ExcludePreset, FileFilter, FileFilterOptions, Walker},
+    ingestion_log::{IngestionLog, LogDestination},
+    ingestion_upload::{ingestion_upload, IngestionOptions},
     progress_reader::{empty_progress_reader, progress_reader_from_path},
 };
 use model::{
+    check_dir_summary::CheckDirSummary,
+    checksum::ChecksumAlgorithm,
     cli_error::CliError,
     cli_output::{CliResult, OutputFormat},
     exit_code::FailureExitCode,
+    forms::create_ingestion::CreateIngestion,
+    hash_file_output::{HashFileMetadataOutput, HashFileOutput},
+    ingestion_status::{IngestionStatus, ProcessingState},
+    ingestion_summary::IngestionSummary,
+    journal_header::JournalHeader,
     lang::Language,
+    listing::{CollectionRow, IngestionRow},
+    login::{StoredLogin, WhoAmI},
+    token_claims::TokenClaims,
     uri::Uri,
 };
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
 use services::giant_api;
 
 mod auth_store;
+mod check_dir;
+mod config;
 mod hash;
 mod ingestion;
 mod model;
 mod services;
 
+const MIB: u64 = 1024 * 1024;
+
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
-    /// Set the output format
-    #[clap(arg_enum, short, long, default_value_t=OutputFormat::Tsv)]
-    format: OutputFormat,
+    /// Set the output format, tsv unless the profile says otherwise
+    #[clap(arg_enum, short, long)]
+    format: Option<OutputFormat>,
+    /// Profile in ~/.giant-utils/config.toml to take defaults from, including the Giant URI
+    /// so that it can be left out. Also set by GIANT_PROFILE.
+    #[clap(long, global = true)]
+    profile: Option<String>,
+    /// Read the Giant auth token from this file rather than GIANT_TOKEN or the one saved by login
+    #[clap(long)]
+    token_file: Option<PathBuf>,
+    /// Maximum number of attempts for each request to the Giant API
+    #[clap(long, default_value = "5")]
+    api_max_attempts: u32,
+    /// Delay before retrying a failed Giant API request, doubled on each subsequent retry
+    #[clap(long, default_value = "500ms")]
+    api_retry_backoff: humantime::Duration,
+    /// Longest to wait before retrying a Giant API request, including when Giant asks for longer
+    #[clap(long, default_value = "30s")]
+    api_max_backoff: humantime::Duration,
+    /// Wait exactly the backoff before each retry, rather than a random amount up to it
+    #[clap(long)]
+    api_no_jitter: bool,
+    /// HTTP statuses from Giant which are worth retrying the request after
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        default_value = "408,429,500,502,503,504",
+        value_parser = parse_status_code
+    )]
+    api_retry_statuses: Vec<StatusCode>,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)] // Only ever constructed once, by clap
 enum Commands {
-    /// Hash a file at the path provided to produce a Giant ID
+    /// Hash files to produce their Giant IDs, printing each as soon as it's done
     Hash {
-        /// The file you wish to hash
-        path: String,
+        /// The files you wish to hash, directories are hashed recursively
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+        /// Number of files to hash at once
+        #[clap(long, default_value = "8")]
+        threads: usize,
+        /// Also print each file's size and modification time
+        #[clap(long)]
+        with_metadata: bool,
     },
     /// Login to the Giant instance at the provided URI with an auth token
     Login {
-        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk
-        giant_uri: Url,
-        /// Your auth token, found on the about page
-        token: String,
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+        /// Your auth token, found on the about page. Prefer --token-stdin, since
+        /// this ends up in your shell history. GIANT_TOKEN is used if no token is given.
+        token: Option<String>,
+        /// Read the token from stdin
+        #[clap(long, conflicts_with = "token")]
+        token_stdin: bool,
+        /// Read the token from this file
+        #[clap(long, conflicts_with_all = &["token", "token-stdin"])]
+        token_file: Option<PathBuf>,
+    },
+    /// Forget the auth token for the Giant instance at the provided URI
+    Logout {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+    },
+    /// List the Giant instances you're logged in to, and when each token expires
+    Logins,
+    /// Show who you're logged in to Giant as, your permissions and when your token expires
+    Whoami {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
     },
     /// Check if the provided hash is in Giant, and you have permission to see it
     CheckHash {
-        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk
-        giant_uri: Url,
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
         /// The resource hash you wish to check exists in Giant
         hash: String,
     },
     /// Check if the provided file is in Giant, and you have permission to see it
     CheckFile {
-        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk
-        giant_uri: Url,
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
         /// The path to the file on your local disk
         path: String,
     },
+    /// Check whether Giant has every file in some directories, printing the status of each
+    CheckDir {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+        /// The files and directories to check, directories are checked recursively
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+        /// Number of files to hash at once
+        #[clap(long, default_value = "8")]
+        hash_threads: usize,
+        /// Number of files to check with Giant at once
+        #[clap(long, default_value = "16")]
+        num_parallel_checks: usize,
+    },
     /// Upload all files in a directory to Giant
     Ingest {
-        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk
-        giant_uri: Url,
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
         /// The ingestion URI for your upload, in the form "collection/ingestion"
         ingestion_uri: String,
         /// The base path for your upload
         path: PathBuf,
-        /// A comma sepearted list of the languages in the files
-        languages: String,
-        /// The bucket you wish to upload to
-        bucket: String,
+        /// A comma separated list of the languages in the files, by name or ISO 639 code, e.g. english,ar
+        // Only given once, so that the optional bucket can come after it
+        #[clap(
+            arg_enum,
+            use_value_delimiter = true,
+            multiple_values = false,
+            multiple_occurrences = false,
+            required = true
+        )]
+        languages: Vec<Language>,
+        /// The bucket you wish to upload to, if not the profile's
+        bucket: Option<String>,
         /// Override the S3 endpoint
         #[clap(long)]
         s3_endpoint: Option<http::Uri>,
-        /// The AWS profile used for connecting to S3
+        /// The AWS profile used for connecting to S3, if not the profile's
+        #[clap(long)]
+        aws_profile: Option<String>,
+        /// The AWS region, if not the profile's [default: eu-west-1]
         #[clap(long)]
-        profile: Option<String>,
-        /// The AWS region
-        #[clap(long, default_value = "eu-west-1")]
-        region: String,
+        region: Option<String>,
         /// Continue from a previous ingestion using its log
         #[clap(short, long)]
         progress_from: Option<PathBuf>,
-        /// Number of parallel file uploads to s3
-        #[clap(short, long, default_value = "32")]
-        num_parallel_uploads: usize,
+        /// Write the log to this file. If it already holds the log of a previous run of
+        /// this ingestion, it is appended to.
+        #[clap(long, conflicts_with = "log-dir")]
+        log_file: Option<PathBuf>,
+        /// Directory to write a new log to, named after the time and the ingestion
+        #[clap(long, default_value = ".")]
+        log_dir: PathBuf,
+        /// Append to the --progress-from log rather than starting a new one
+        #[clap(long, requires = "progress-from", conflicts_with_all = &["log-file", "log-dir"])]
+        append: bool,
+        /// Number of parallel file uploads to s3, if not the profile's [default: 32]
+        #[clap(short, long)]
+        num_parallel_uploads: Option<usize>,
+        /// Maximum number of attempts for each stage of a file's upload to S3
+        #[clap(long, default_value = "3")]
+        upload_max_attempts: u32,
+        /// Upload files of at least this many MiB in parts
+        #[clap(long, default_value = "100")]
+        multipart_threshold_mib: u64,
+        /// Size of each part of a multipart upload in MiB, from S3's minimum of 5 up to its
+        /// maximum of 5120. Raised if a file would need over 10,000 parts.
+        #[clap(long, default_value = "64", value_parser = clap::value_parser!(u64).range(5..=MAX_PART_SIZE / MIB))]
+        multipart_part_size_mib: u64,
+        /// Number of parts of a single file to upload at once
+        #[clap(long, default_value = "4")]
+        multipart_concurrency: usize,
+        /// Leave incomplete multipart uploads in S3 when a file fails,
+        /// so resuming with --progress-from can reuse the parts already uploaded
+        #[clap(long)]
+        keep_incomplete_uploads: bool,
+        /// Checksum S3 verifies each upload against, recorded in the log.
+        /// Use md5 for S3 compatible stores which don't support the others.
+        #[clap(arg_enum, long, default_value_t=ChecksumAlgorithm::Sha256)]
+        checksum: ChecksumAlgorithm,
+        /// Only upload files matching one of these globs, e.g. '*.pdf'.
+        /// Globs are matched against both the file name and the path relative to the base path.
+        #[clap(long)]
+        include: Vec<String>,
+        /// Don't upload files or directories matching this glob, e.g. '*.tmp' or 'drafts/**'
+        #[clap(long)]
+        exclude: Vec<String>,
+        /// Don't upload common junk files
+        #[clap(arg_enum, long, use_value_delimiter = true)]
+        exclude_preset: Vec<ExcludePreset>,
+        /// Don't honour .giantignore files, which use .gitignore syntax and apply to their directory
+        #[clap(long)]
+        no_giantignore: bool,
+        /// Don't upload files smaller than this many bytes
+        #[clap(long)]
+        min_size: Option<u64>,
+        /// Don't upload files larger than this many bytes
+        #[clap(long)]
+        max_size: Option<u64>,
+        /// Record each excluded file and directory in the log
+        #[clap(long)]
+        log_excluded: bool,
+        /// How to walk the directory. Parallel is faster on SSDs and network drives,
+        /// sequential avoids thrashing spinning disks.
+        #[clap(arg_enum, long, default_value_t=Walker::Sequential)]
+        walker: Walker,
+        /// Number of directories the parallel walker reads at once
+        #[clap(long, default_value = "8")]
+        walker_threads: usize,
+        /// After Ctrl-C or SIGTERM, how long to let uploads in progress finish before interrupting them
+        #[clap(long, default_value = "60s")]
+        shutdown_timeout: humantime::Duration,
+        /// Report what would be uploaded without creating anything in Giant or S3.
+        /// With --skip-existing, also hash each file to count those already in Giant.
+        #[clap(long)]
+        dry_run: bool,
+        /// Hash each file first and don't upload those already in Giant.
+        /// Skipped files will not appear in this ingestion.
+        #[clap(long)]
+        skip_existing: bool,
     },
-    /// List the blobs in a collection.
-    /// **Currently only lists up to 500 blobs**
+    /// List every blob in a collection, printing each as it is fetched
     ListBlobs {
-        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk
-        giant_uri: Url,
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
         /// The collection whose blobs you want to list
         collection: String,
         /// List all blobs, or filter to only those that also exist in collections other
@@ -105,85 +290,466 @@ enum Commands {
         #[clap(arg_enum, short, long, default_value_t=ListBlobsFilter::All)]
         filter: ListBlobsFilter,
     },
+    /// List the collections you can see, one per line
+    ListCollections {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+    },
+    /// List the ingestions in a collection, one per line
+    ListIngestions {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+        /// The collection whose ingestions you want to list
+        collection: String,
+    },
+    /// Create a collection, failing if it already exists
+    CreateCollection {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+        /// The name of the collection
+        collection: String,
+        /// Succeed without changing anything if the collection already exists
+        #[clap(long)]
+        if_not_exists: bool,
+    },
+    /// Create an ingestion in an existing collection, failing if it already exists
+    CreateIngestion {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+        /// The ingestion to create, in the form collection/ingestion
+        ingestion_uri: String,
+        /// A comma separated list of the languages in the files, by name or ISO 639 code, e.g. english,ar
+        #[clap(arg_enum, long, use_value_delimiter = true, required = true)]
+        languages: Vec<Language>,
+        /// The original location of the files, shown in Giant
+        #[clap(long)]
+        path: Option<PathBuf>,
+        /// Stop any more files being added to the ingestion
+        #[clap(long)]
+        fixed: bool,
+        /// Make this the collection's default ingestion for files uploaded through Giant
+        #[clap(long)]
+        default: bool,
+        /// Succeed without changing anything if the ingestion already exists
+        #[clap(long)]
+        if_not_exists: bool,
+    },
+    /// Report how far Giant has got processing an ingestion's files
+    IngestionStatus {
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
+        /// The ingestion, in the form collection/ingestion
+        ingestion_uri: String,
+        /// Keep checking, printing the status each time, until processing finishes
+        #[clap(long)]
+        watch: bool,
+        /// How often to check when watching
+        #[clap(long, default_value = "30s")]
+        interval: humantime::Duration,
+    },
     /// Delete a collection and all its contents
     DeleteCollection {
-        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk
-        giant_uri: Url,
+        /// The URI of your Giant server, e.g. https://playground.pfi.gutools.co.uk.
+        /// Can be left out to use the profile's.
+        #[clap(required = true, value_parser = parse_giant_uri)]
+        giant_uri: Option<Url>,
         /// The collection you want to delete
         collection: String,
     },
 }
 
+/// The positional argument each command that talks to Giant takes its URI in
+const GIANT_URI_ARG: &str = "giant-uri";
+
+/// Parse the command line, allowing the Giant URI to be left out in favour of the profile's.
+/// Clap can't leave out a positional argument that others come after, so if the first one
+/// given isn't a Giant URI, the Giant URI is moved to the end where it can be left out.
+fn parse_cli() -> (Cli, Profile) {
+    let args: Vec<OsString> = env::args_os().collect();
+    let cli = parse_args(&args).unwrap_or_else(|e| e.exit());
+    let profile = load_profile(cli.profile.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        // Before there were config profiles, ingest took the AWS profile with --profile
+        if cli.profile.is_some() && matches!(cli.command, Commands::Ingest { .. }) {
+            eprintln!("The AWS profile for ingest is now set with --aws-profile");
+        }
+        std::process::exit(FailureExitCode::Config as i32);
+    });
+    (cli, profile)
+}
+
+fn parse_args(args: &[OsString]) -> Result<Cli, clap::Error> {
+    let command = if giant_uri_omitted(args) {
+        // mut_arg moves it after the other arguments, so it becomes the last positional
+        map_giant_uri_commands(Cli::command(), |command| {
+            command.mut_arg(GIANT_URI_ARG, |arg| {
+                arg.last(true).required(false).hide(true)
+            })
+        })
+    } else {
+        Cli::command()
+    };
+    Cli::from_arg_matches(&command.try_get_matches_from(args)?)
+}
+
+/// Whether the command takes a Giant URI, but the first positional argument it was given isn't one
+fn giant_uri_omitted(args: &[OsString]) -> bool {
+    // Only looking at how clap splits up the arguments, anything wrong with them is
+    // reported by the real parse
+    let command = map_giant_uri_commands(Cli::command(), |command| {
+        let positionals: Vec<&str> = command.get_positionals().map(|a| a.get_id()).collect();
+        // mut_arg moves each argument to the end, so going through them all keeps their order
+        positionals.into_iter().fold(command, |command, id| {
+            command.mut_arg(id, |arg| {
+                if id == GIANT_URI_ARG {
+                    arg.value_parser(clap::value_parser!(String))
+                } else {
+                    arg
+                }
+            })
+        })
+    })
+    .ignore_errors(true);
+    let matches = match command.try_get_matches_from(args) {
+        Ok(matches) => matches,
+        Err(_) => return false,
+    };
+
+    matches
+        .subcommand()
+        .and_then(|(_, subcommand)| subcommand.try_get_one::<String>(GIANT_URI_ARG).ok())
+        .flatten()
+        .is_some_and(|first| parse_giant_uri(first).is_err())
+}
+
+fn map_giant_uri_commands(
+    mut command: clap::Command<'static>,
+    f: impl Fn(clap::Command<'static>) -> clap::Command<'static>,
+) -> clap::Command<'static> {
+    for subcommand in command.get_subcommands_mut() {
+        if subcommand
+            .get_arguments()
+            .any(|arg| arg.get_id() == GIANT_URI_ARG)
+        {
+            *subcommand = f(std::mem::take(subcommand));
+        }
+    }
+    command
+}
+
+/// The token to log in with. Only one way of passing it can be given on the command line,
+/// and GIANT_TOKEN is only used if none was.
+fn login_token(
+    token: Option<String>,
+    token_stdin: bool,
+    token_file: Option<PathBuf>,
+) -> Result<String, CliError> {
+    let token = if token_stdin {
+        let mut token = String::new();
+        std::io::stdin().read_to_string(&mut token)?;
+        Some(token)
+    } else if let Some(token_file) = token_file {
+        Some(auth_store::read_token_file(&token_file)?)
+    } else {
+        token.or_else(|| env::var(auth_store::TOKEN_ENV_VAR).ok())
+    };
+
+    token.and_then(auth_store::non_empty).ok_or_else(|| {
+        CliError::InputError(
+            "No token given, pass one with --token-stdin, --token-file or GIANT_TOKEN".to_owned(),
+        )
+    })
+}
+
+fn exit_with_config_error(e: CliError) -> ! {
+    eprintln!("{e}");
+    std::process::exit(FailureExitCode::Config as i32);
+}
+
 #[tokio::main]
 async fn main() {
-    let cli = Cli::parse();
+    let (cli, profile) = parse_cli();
 
-    let format = &cli.format;
+    let format = &cli
+        .format
+        .clone()
+        .or_else(|| profile.format.clone())
+        .unwrap_or(OutputFormat::Tsv);
+    let retry_policy = RetryPolicy {
+        max_backoff: cli.api_max_backoff.into(),
+        jitter: !cli.api_no_jitter,
+        retryable_statuses: cli.api_retry_statuses.clone(),
+        ..RetryPolicy::new(cli.api_max_attempts, cli.api_retry_backoff.into())
+    };
+    let token_file = cli.token_file.clone();
+    let env_token = env::var(auth_store::TOKEN_ENV_VAR).ok();
+    let profile_giant_uri = profile
+        .giant_uri()
+        .unwrap_or_else(|e| exit_with_config_error(e));
+    let giant_uri_or_profile = |giant_uri: Option<Url>| {
+        giant_uri
+            .or_else(|| profile_giant_uri.clone())
+            .ok_or_else(|| {
+                CliError::InputError(
+                    "No Giant URI given, and the profile doesn't have one".to_owned(),
+                )
+            })
+    };
+    let api_client = |giant_uri: Option<Url>| -> Result<GiantApiClient, CliError> {
+        let giant_uri = giant_uri_or_profile(giant_uri)?;
+        let auth_token =
+            auth_store::resolve(giant_uri.as_str(), token_file.as_deref(), env_token.clone())?;
+        GiantApiClient::new(giant_uri, auth_token, retry_policy.clone())
+    };
 
     match cli.command {
-        Commands::Hash { path } => {
-            CliResult::new(hash_file(path), FailureExitCode::Hash).print_or_exit(format);
+        Commands::Hash {
+            paths,
+            threads,
+            with_metadata,
+        } => {
+            let mut failures = 0;
+            hash_paths(&paths, threads, |result| {
+                match result {
+                    Ok(file) if with_metadata => format.print_item(&HashFileMetadataOutput {
+                        hash: file.hash,
+                        path: file.path.display().to_string(),
+                        size: file.size,
+                        modified: file
+                            .modified
+                            .map(|modified| DateTime::<Utc>::from(modified).to_rfc3339())
+                            .unwrap_or_default(),
+                    }),
+                    Ok(file) => format.print_item(&HashFileOutput::new(
+                        file.hash,
+                        file.path.display().to_string(),
+                    )),
+                    Err((path, e)) => {
+                        failures += 1;
+                        eprintln!("Failed to hash {}: {e}", path.display());
+                    }
+                }
+                true
+            });
+
+            if failures > 0 {
+                eprintln!("Couldn't hash {failures} of the files");
+                std::process::exit(FailureExitCode::Hash as i32);
+            }
+        }
+        Commands::Login {
+            giant_uri,
+            token,
+            token_stdin,
+            token_file,
+        } => {
+            let result = giant_uri_or_profile(giant_uri).and_then(|giant_uri| {
+                let token = login_token(token, token_stdin, token_file.or(cli.token_file))?;
+                auth_store::set(giant_uri.as_str(), &token)
+            });
+            CliResult::new(result, FailureExitCode::SetAuthToken).exit();
+        }
+        Commands::Logout { giant_uri } => {
+            let result = giant_uri_or_profile(giant_uri).and_then(|giant_uri| {
+                if !auth_store::delete(giant_uri.as_str())? {
+                    eprintln!("You weren't logged in to {giant_uri}");
+                }
+                Ok(())
+            });
+            CliResult::new(result, FailureExitCode::SetAuthToken).exit();
         }
-        Commands::Login { giant_uri, token } => {
-            CliResult::new(
-                auth_store::set(giant_uri.as_str(), &token),
-                FailureExitCode::SetAuthToken,
-            )
-            .exit();
+        Commands::Logins => {
+            let result: Result<(), CliError> = (|| {
+                for giant_uri in auth_store::list()? {
+                    let claims = auth_store::peek(&giant_uri)
+                        .and_then(|token| TokenClaims::decode(&token))
+                        .ok();
+                    format.print_item(&StoredLogin::new(&giant_uri, claims.as_ref()));
+                }
+                Ok(())
+            })();
+
+            CliResult::new(result, FailureExitCode::SetAuthToken).exit();
+        }
+        Commands::Whoami { giant_uri } => {
+            let result: Result<WhoAmI, CliError> = async {
+                let giant_uri = giant_uri_or_profile(giant_uri)?;
+                let auth_token = auth_store::resolve(
+                    giant_uri.as_str(),
+                    token_file.as_deref(),
+                    env_token.clone(),
+                )?;
+                let claims = TokenClaims::decode(&auth_token.token).ok();
+                let client =
+                    GiantApiClient::new(giant_uri.clone(), auth_token, retry_policy.clone())?;
+                let permissions = client.get_current_user_permissions().await?;
+                Ok(WhoAmI::new(
+                    giant_uri.as_str(),
+                    claims.as_ref(),
+                    permissions,
+                ))
+            }
+            .await;
+
+            CliResult::new(result, FailureExitCode::Api).print_or_exit(format);
         }
         Commands::CheckHash { giant_uri, hash } => {
-            let mut client = GiantApiClient::new(giant_uri.clone());
-            CliResult::new(client.check_hash_exists(&hash).await, FailureExitCode::Api)
-                .print_or_exit(format);
+            let result = async { api_client(giant_uri)?.check_hash_exists(&hash).await }.await;
+            CliResult::new(result, FailureExitCode::Api).print_or_exit(format);
         }
         Commands::CheckFile { giant_uri, path } => {
-            let file_exists = (|| async {
-                let mut client = GiantApiClient::new(giant_uri.clone());
+            let file_exists = async {
+                let client = api_client(giant_uri)?;
                 let hash = hash_file(path.clone())?;
                 client.check_hash_exists(&hash.hash).await
-            })()
+            }
             .await;
 
             CliResult::new(file_exists, FailureExitCode::Api).print_or_exit(format);
         }
+        Commands::CheckDir {
+            giant_uri,
+            paths,
+            hash_threads,
+            num_parallel_checks,
+        } => {
+            let result = async {
+                let client = api_client(giant_uri)?;
+                let check = |hash: String| {
+                    let client = &client;
+                    async move { client.get_hash_status(&hash).await }
+                };
+                check_dir(check, paths, hash_threads, num_parallel_checks, format).await
+            }
+            .await;
+
+            // Missing files still get a summary, but need to stand out to scripts
+            let all_present = result.as_ref().map_or(true, CheckDirSummary::all_present);
+            CliResult::new(result, FailureExitCode::Api).print_or_exit(format);
+            if !all_present {
+                std::process::exit(FailureExitCode::FilesMissing as i32);
+            }
+        }
         Commands::Ingest {
             giant_uri,
             ingestion_uri,
             path,
             languages,
             bucket,
-            profile,
+            aws_profile,
             region,
             s3_endpoint,
             progress_from,
+            log_file,
+            log_dir,
+            append,
             num_parallel_uploads,
+            upload_max_attempts,
+            skip_existing,
+            multipart_threshold_mib,
+            multipart_part_size_mib,
+            multipart_concurrency,
+            keep_incomplete_uploads,
+            checksum,
+            include,
+            exclude,
+            exclude_preset,
+            no_giantignore,
+            min_size,
+            max_size,
+            log_excluded,
+            dry_run,
+            walker,
+            walker_threads,
+            shutdown_timeout,
         } => {
-            // I'm sure we can do better than this.
-            let languages: Vec<Language> = languages
-                .split(',')
-                .map(|l| match l {
-                    "arabic" => Language::Arabic,
-                    "english" => Language::English,
-                    "french" => Language::French,
-                    "german" => Language::German,
-                    "portuguese" => Language::Portuguese,
-                    "russian" => Language::Russian,
-                    "persian" => Language::Persian,
-                    _ => panic!("Invalid language!"),
-                })
-                .collect();
-
-            let result: Result<(), CliError> = (|| async {
-                let mut client = GiantApiClient::new(giant_uri.clone());
-                let progress_reader = match progress_from {
-                    Some(path) => progress_reader_from_path(path)?,
+            let num_parallel_uploads = num_parallel_uploads
+                .or(profile.num_parallel_uploads)
+                .unwrap_or(32);
+            let filter_options = FileFilterOptions {
+                include,
+                exclude,
+                presets: exclude_preset,
+                min_size,
+                max_size,
+                use_ignore_files: !no_giantignore,
+            };
+
+            if dry_run {
+                let result = async {
+                    let ingestion_uri = Uri::parse(&ingestion_uri)?;
+                    let filter = FileFilter::new(filter_options)?;
+                    // Only needs a token if it's checking Giant
+                    let client = skip_existing.then(|| api_client(giant_uri)).transpose()?;
+                    ingestion::dry_run::dry_run(
+                        &ingestion_uri,
+                        &path,
+                        &filter,
+                        walker,
+                        walker_threads,
+                        client.as_ref(),
+                        num_parallel_uploads,
+                    )
+                    .await
+                }
+                .await;
+
+                CliResult::new(result, FailureExitCode::Upload).print_or_exit(format);
+                return;
+            }
+
+            let result: Result<IngestionSummary, CliError> = async {
+                let ingestion_uri = Uri::parse(&ingestion_uri)?;
+                let s3_endpoint = match s3_endpoint {
+                    Some(endpoint) => Some(endpoint),
+                    None => profile.s3_endpoint()?,
+                };
+                let bucket = bucket.or(profile.bucket).ok_or_else(|| {
+                    CliError::InputError(
+                        "No bucket given, and the profile doesn't have one".to_owned(),
+                    )
+                })?;
+                let region = region
+                    .or(profile.region)
+                    .unwrap_or_else(|| "eu-west-1".to_owned());
+                let aws_profile = aws_profile.or(profile.aws_profile);
+                let journal_header = JournalHeader::new(&ingestion_uri, &path)?;
+                let filter = FileFilter::new(filter_options)?;
+
+                let client = api_client(giant_uri)?;
+                let progress_reader = match &progress_from {
+                    Some(log_path) => progress_reader_from_path(log_path, &journal_header)?,
                     None => empty_progress_reader(),
                 };
 
-                let ingestion_uri = Uri::parse(&ingestion_uri)?;
+                // Opened up front so that we don't get as far as creating the ingestion if it can't be
+                let log_destination =
+                    match (log_file, append.then_some(progress_from.as_ref()).flatten()) {
+                        (Some(log_file), _) => LogDestination::File(log_file),
+                        (None, Some(progress_from)) => LogDestination::File(progress_from.clone()),
+                        (None, None) => LogDestination::Dir(log_dir),
+                    };
+                let log =
+                    IngestionLog::open(log_destination, &ingestion_uri, &journal_header, format)
+                        .await?;
+                // The log we're resuming from already records the files it finished
+                let log_already_processed = !progress_from
+                    .map(|progress_from| log.appending && log.is_same_file(&progress_from))
+                    .unwrap_or(false);
+
                 let collection = client.get_or_insert_collection(&ingestion_uri).await?;
 
-                println!("Checking ingestion");
+                eprintln!("Checking ingestion");
                 client
                     .get_or_insert_ingestion(
                         &ingestion_uri,
@@ -193,53 +759,228 @@ async fn main() {
                     )
                     .await?;
 
+                let multipart = MultipartConfig {
+                    threshold: multipart_threshold_mib * MIB,
+                    part_size: multipart_part_size_mib * MIB,
+                    concurrency: multipart_concurrency,
+                };
                 let s3_client = if let Some(endpoint) = s3_endpoint {
-                    S3Client::from_endpoint(endpoint, &bucket, region, profile).await
+                    S3Client::from_endpoint(
+                        endpoint,
+                        &bucket,
+                        region,
+                        aws_profile,
+                        multipart,
+                        checksum,
+                    )
+                    .await
                 } else {
-                    S3Client::new(&bucket, region, profile).await
+                    S3Client::new(&bucket, region, aws_profile, multipart, checksum).await
                 };
 
-                println!("Starting crawl");
+                eprintln!("Starting crawl");
                 ingestion_upload(
                     ingestion_uri,
                     &languages,
                     path,
                     s3_client,
                     progress_reader,
-                    format,
-                    num_parallel_uploads,
+                    log,
+                    IngestionOptions {
+                        num_parallel_uploads,
+                        upload_retry_policy: RetryPolicy::new(
+                            upload_max_attempts,
+                            Duration::from_secs(1),
+                        ),
+                        skip_existing: skip_existing.then_some(client),
+                        keep_incomplete_uploads,
+                        filter,
+                        log_excluded,
+                        walker,
+                        walker_threads,
+                        shutdown_timeout: shutdown_timeout.into(),
+                        log_already_processed,
+                        journal_header,
+                    },
                 )
                 .await
-            })()
+            }
             .await;
 
+            // Some files failing still gets a summary, but needs to stand out to scripts
+            let partial_failure = result.as_ref().ok().and_then(|s| s.failure_exit_code());
             CliResult::new(result, FailureExitCode::Upload).print_or_exit(format);
+            if let Some(exit_code) = partial_failure {
+                std::process::exit(exit_code as i32);
+            }
+        }
+        Commands::ListCollections { giant_uri } => {
+            let result: Result<(), CliError> = async {
+                let client = api_client(giant_uri)?;
+                for collection in client.list_collections().await? {
+                    format.print_item(&CollectionRow::from(&collection));
+                }
+
+                Ok(())
+            }
+            .await;
+
+            CliResult::new(result, FailureExitCode::Api).exit();
+        }
+        Commands::ListIngestions {
+            giant_uri,
+            collection,
+        } => {
+            let result: Result<(), CliError> = async {
+                let client = api_client(giant_uri)?;
+                let collection = client.get_collection(&collection).await?;
+                for ingestion in &collection.ingestions {
+                    format.print_item(&IngestionRow::new(&collection, ingestion));
+                }
+
+                Ok(())
+            }
+            .await;
+
+            CliResult::new(result, FailureExitCode::Api).exit();
         }
-        // Currently this command will only list up to 500 blobs,
-        // due to restrictions in the Giant API.
         Commands::ListBlobs {
             giant_uri,
             collection,
             filter,
         } => {
-            let mut client = GiantApiClient::new(giant_uri.clone());
-            CliResult::new(
-                client.get_blobs_in_collection(&collection, &filter).await,
-                FailureExitCode::Api,
-            )
-            .print_or_exit(format);
+            let result: Result<(), CliError> = async {
+                let client = api_client(giant_uri)?;
+                let mut blobs = Box::pin(client.stream_blobs_in_collection(&collection, &filter));
+
+                while let Some(blob) = blobs.try_next().await? {
+                    format.print_item(&blob);
+                }
+
+                Ok(())
+            }
+            .await;
+
+            CliResult::new(result, FailureExitCode::Api).exit();
+        }
+        Commands::CreateCollection {
+            giant_uri,
+            collection,
+            if_not_exists,
+        } => {
+            let result: Result<CollectionRow, CliError> = async {
+                let client = api_client(giant_uri)?;
+                let existing = match client.get_collection(&collection).await {
+                    Ok(existing) => Some(existing),
+                    Err(CliError::CollectionNotFound(_)) => None,
+                    Err(e) => return Err(e),
+                };
+
+                match existing {
+                    Some(existing) if if_not_exists => Ok(CollectionRow::from(&existing)),
+                    Some(_) => Err(CliError::AlreadyExists(collection)),
+                    None => Ok(CollectionRow::from(
+                        &client.create_collection(&collection).await?,
+                    )),
+                }
+            }
+            .await;
+
+            CliResult::new(result, FailureExitCode::Api).print_or_exit(format);
+        }
+        Commands::CreateIngestion {
+            giant_uri,
+            ingestion_uri,
+            languages,
+            path,
+            fixed,
+            default,
+            if_not_exists,
+        } => {
+            let result: Result<IngestionRow, CliError> = async {
+                let ingestion_uri = Uri::parse(&ingestion_uri)?;
+                let client = api_client(giant_uri)?;
+                let collection = client.get_collection(ingestion_uri.collection()).await?;
+
+                if collection.ingestion(&ingestion_uri).is_some() {
+                    if !if_not_exists {
+                        return Err(CliError::AlreadyExists(ingestion_uri.as_str().to_owned()));
+                    }
+                } else {
+                    let create_ingestion = CreateIngestion {
+                        path,
+                        name: Some(ingestion_uri.ingestion().to_owned()),
+                        languages,
+                        fixed: Some(fixed),
+                        default: Some(default),
+                    };
+                    client
+                        .create_ingestion(&ingestion_uri, &create_ingestion)
+                        .await?;
+                }
+
+                // Giant doesn't return the new ingestion, so fetch it to show what we made
+                let collection = client.get_collection(ingestion_uri.collection()).await?;
+                match collection.ingestion(&ingestion_uri) {
+                    Some(ingestion) => Ok(IngestionRow::new(&collection, ingestion)),
+                    None => Err(CliError::UnexpectedResponse(StatusCode::OK)),
+                }
+            }
+            .await;
+
+            CliResult::new(result, FailureExitCode::Api).print_or_exit(format);
+        }
+        Commands::IngestionStatus {
+            giant_uri,
+            ingestion_uri,
+            watch,
+            interval,
+        } => {
+            let result: Result<ProcessingState, CliError> = async {
+                let ingestion_uri = Uri::parse(&ingestion_uri)?;
+                let client = api_client(giant_uri)?;
+
+                loop {
+                    let collection = client.get_collection(ingestion_uri.collection()).await?;
+                    let ingestion = collection.ingestion(&ingestion_uri).ok_or_else(|| {
+                        CliError::IngestionNotFound(ingestion_uri.as_str().to_owned())
+                    })?;
+                    let events = client.get_ingestion_events(&ingestion_uri).await?;
+
+                    let status = IngestionStatus::new(ingestion, &events);
+                    format.print_item(&status);
+
+                    let state = status.processing_state();
+                    if !watch || state != ProcessingState::Processing {
+                        return Ok(state);
+                    }
+                    tokio::time::sleep(interval.into()).await;
+                }
+            }
+            .await;
+
+            match result {
+                Ok(ProcessingState::Failed) => {
+                    std::process::exit(FailureExitCode::ProcessingFailed as i32)
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(FailureExitCode::Api as i32);
+                }
+            }
         }
         Commands::DeleteCollection {
             giant_uri,
             collection,
         } => {
-            let result: Result<(), CliError> = (|| async {
-                let mut client = GiantApiClient::new(giant_uri.clone());
+            let result: Result<(), CliError> = async {
+                let client = api_client(giant_uri)?;
 
-                // Returns a maximum of 500 results,
-                // so we need to loop until we've deleted them all.
+                // Deleting shifts every later page down, so we keep
+                // re-fetching the first page until the collection is empty.
                 let mut blobs = client
-                    .get_blobs_in_collection(&collection, &ListBlobsFilter::All)
+                    .get_blobs_in_collection(&collection, &ListBlobsFilter::All, 1)
                     .await?;
 
                 while !blobs.is_empty() {
@@ -263,7 +1004,7 @@ async fn main() {
                         println!("Deleted blob {}", blob.uri);
                     }
                     blobs = client
-                        .get_blobs_in_collection(&collection, &ListBlobsFilter::All)
+                        .get_blobs_in_collection(&collection, &ListBlobsFilter::All, 1)
                         .await?;
                 }
 
@@ -272,10 +1013,73 @@ async fn main() {
                 println!("Deleted collection {collection}");
 
                 Ok(())
-            })()
+            }
             .await;
 
             CliResult::new(result, FailureExitCode::Api).print_or_exit(format);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, clap::Error> {
+        let args: Vec<OsString> = args.split(' ').map(OsString::from).collect();
+        parse_args(&args)
+    }
+
+    fn giant_uri(cli: Cli) -> Option<String> {
+        match cli.command {
+            Commands::ListBlobs { giant_uri, .. }
+            | Commands::CheckDir { giant_uri, .. }
+            | Commands::Ingest { giant_uri, .. }
+            | Commands::Login { giant_uri, .. }
+            | Commands::ListCollections { giant_uri } => giant_uri.map(String::from),
+            _ => panic!("not a command these tests use"),
+        }
+    }
+
+    #[test]
+    fn giant_uri_can_be_left_out() {
+        let uri = Some("https://giant.example.com/".to_owned());
+        for given in [
+            "giant-utils list-blobs https://giant.example.com coll",
+            "giant-utils list-collections https://giant.example.com",
+            "giant-utils check-dir https://giant.example.com a b",
+            "giant-utils ingest https://giant.example.com c/i . english,ar bucket",
+            "giant-utils login https://giant.example.com token",
+        ] {
+            assert_eq!(giant_uri(parse(given).unwrap()), uri, "{given}");
+        }
+
+        for omitted in [
+            "giant-utils list-blobs coll --profile prod",
+            "giant-utils list-collections",
+            "giant-utils check-dir a b",
+            "giant-utils ingest c/i . english,ar bucket",
+            "giant-utils login token",
+        ] {
+            assert_eq!(giant_uri(parse(omitted).unwrap()), None, "{omitted}");
+        }
+
+        match parse("giant-utils ingest c/i . en --aws-profile s3")
+            .unwrap()
+            .command
+        {
+            Commands::Ingest {
+                ingestion_uri,
+                bucket,
+                aws_profile,
+                ..
+            } => {
+                assert_eq!(ingestion_uri, "c/i");
+                assert_eq!(bucket, None);
+                assert_eq!(aws_profile.as_deref(), Some("s3"));
+            }
+            _ => unreachable!(),
+        }
+        assert!(parse("giant-utils list-blobs").is_err());
+    }
+}
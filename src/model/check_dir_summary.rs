@@ -0,0 +1,19 @@
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::Serialize;
+
+/// Printed at the end of check-dir, after a row for each file
+#[derive(Serialize, Reflection, Debug, Default)]
+pub struct CheckDirSummary {
+    pub present: u64,
+    pub missing: u64,
+    pub forbidden: u64,
+    // Couldn't be hashed, or Giant couldn't be asked about them
+    pub failed: u64,
+}
+
+impl CheckDirSummary {
+    pub fn all_present(&self) -> bool {
+        self.missing == 0 && self.forbidden == 0 && self.failed == 0
+    }
+}
@@ -0,0 +1,29 @@
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashStatus {
+    Present,
+    Missing,
+    // Giant has it, but not anywhere you can see
+    Forbidden,
+}
+
+impl HashStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashStatus::Present => "present",
+            HashStatus::Missing => "missing",
+            HashStatus::Forbidden => "forbidden",
+        }
+    }
+}
+
+/// One row of check-dir's output
+#[derive(Serialize, Reflection)]
+pub struct CheckedFile {
+    pub status: String,
+    pub hash: String,
+    pub path: String,
+}
@@ -0,0 +1,227 @@
+use std::{fmt, mem};
+
+use aws_smithy_checksums::http::HttpChecksum;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// The checksums S3 can verify an upload against
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Crc32c,
+    /// Sent as Content-MD5, for S3 compatible stores without the newer checksum headers
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Crc32c => "crc32c",
+            Self::Md5 => "md5",
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn HttpChecksum> {
+        let algorithm = match self {
+            Self::Sha256 => aws_smithy_checksums::ChecksumAlgorithm::Sha256,
+            Self::Crc32c => aws_smithy_checksums::ChecksumAlgorithm::Crc32c,
+            Self::Md5 => aws_smithy_checksums::ChecksumAlgorithm::Md5,
+        };
+        algorithm.into_impl()
+    }
+
+    pub fn of_bytes(&self, bytes: &[u8]) -> Checksum {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        Checksum {
+            algorithm: *self,
+            digest: hasher.finalize().to_vec(),
+        }
+    }
+
+    /// The checksum S3 gives a multipart object: the checksum of its parts' checksums
+    pub fn of_parts(&self, parts: &[Checksum]) -> Checksum {
+        let digests: Vec<u8> = parts.iter().flat_map(|p| p.digest.clone()).collect();
+        self.of_bytes(&digests)
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    digest: Vec<u8>,
+}
+
+impl Checksum {
+    /// The value sent to S3 with the upload, always base64 even for Content-MD5
+    pub fn header_value(&self) -> String {
+        base64::encode(&self.digest)
+    }
+
+    /// The value as S3 reports it back to us. MD5 comes back as the ETag, which is hex.
+    pub fn s3_value(&self) -> String {
+        match self.algorithm {
+            ChecksumAlgorithm::Md5 => self.digest.iter().map(|b| format!("{b:02x}")).collect(),
+            _ => self.header_value(),
+        }
+    }
+}
+
+/// Checksums each part of a file as it's read, so that they can be computed in the
+/// same read as the file's Giant hash. A file uploaded in one go is a single part.
+pub struct PartChecksums {
+    algorithm: ChecksumAlgorithm,
+    /// None if the file is uploaded in one go
+    part_size: Option<u64>,
+    hasher: Box<dyn HttpChecksum>,
+    in_current_part: u64,
+    size: u64,
+    parts: Vec<Checksum>,
+}
+
+/// The checksums of every part of a file, in order
+pub struct FileChecksums {
+    pub part_size: Option<u64>,
+    /// How much of the file was read, to check it hasn't changed by the time it's uploaded
+    pub size: u64,
+    pub parts: Vec<Checksum>,
+}
+
+impl PartChecksums {
+    pub fn new(algorithm: ChecksumAlgorithm, part_size: Option<u64>) -> Self {
+        PartChecksums {
+            algorithm,
+            part_size,
+            hasher: algorithm.hasher(),
+            in_current_part: 0,
+            size: 0,
+            parts: vec![],
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.size += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let room = self
+                .part_size
+                .map_or(u64::MAX, |part_size| part_size - self.in_current_part);
+            let (in_part, rest) = bytes.split_at(room.min(bytes.len() as u64) as usize);
+
+            self.hasher.update(in_part);
+            self.in_current_part += in_part.len() as u64;
+            if Some(self.in_current_part) == self.part_size {
+                self.finish_part();
+            }
+            bytes = rest;
+        }
+    }
+
+    fn finish_part(&mut self) {
+        let hasher = mem::replace(&mut self.hasher, self.algorithm.hasher());
+        self.parts.push(Checksum {
+            algorithm: self.algorithm,
+            digest: hasher.finalize().to_vec(),
+        });
+        self.in_current_part = 0;
+    }
+
+    pub fn finish(mut self) -> FileChecksums {
+        // An empty file is still one (empty) part
+        if self.in_current_part > 0 || self.parts.is_empty() {
+            self.finish_part();
+        }
+        FileChecksums {
+            part_size: self.part_size,
+            size: self.size,
+            parts: self.parts,
+        }
+    }
+}
+
+/// How S3 verified an uploaded object, recorded in the ingestion log
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// As S3 reports it, with a `-<part count>` suffix for multipart uploads
+    pub checksum: String,
+    pub e_tag: String,
+}
+
+/// Compare checksums as S3 reports them, ignoring quotes around ETags
+/// and the part count suffix, which not every S3 compatible store includes
+pub fn s3_checksums_match(expected: &str, returned: &str) -> bool {
+    let strip = |value: &str| -> String {
+        let value = value.trim_matches('"');
+        value.split('-').next().unwrap_or(value).to_owned()
+    };
+    strip(expected) == strip(returned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn md5_matches_etag_format() {
+        let checksum = ChecksumAlgorithm::Md5.of_bytes(b"hello world");
+        assert_eq!(checksum.s3_value(), "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert_eq!(checksum.header_value(), "XrY7u+Ae7tCTyyK7j1rNww==");
+        assert!(s3_checksums_match(
+            &checksum.s3_value(),
+            "\"5eb63bbbe01eeed093cb22bb8f5acdc3\""
+        ));
+    }
+
+    #[test]
+    fn sha256_is_base64() {
+        let checksum = ChecksumAlgorithm::Sha256.of_bytes(b"hello world");
+        assert_eq!(
+            checksum.s3_value(),
+            "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+        );
+    }
+
+    #[test]
+    fn checksums_each_part_however_the_file_is_read() {
+        let contents = b"abcdefghij";
+        let mut checksums = PartChecksums::new(ChecksumAlgorithm::Sha256, Some(4));
+        for chunk in contents.chunks(3) {
+            checksums.update(chunk);
+        }
+        let checksums = checksums.finish();
+
+        assert_eq!(checksums.size, 10);
+        assert_eq!(
+            checksums.parts,
+            vec![
+                ChecksumAlgorithm::Sha256.of_bytes(b"abcd"),
+                ChecksumAlgorithm::Sha256.of_bytes(b"efgh"),
+                ChecksumAlgorithm::Sha256.of_bytes(b"ij"),
+            ]
+        );
+
+        let mut whole = PartChecksums::new(ChecksumAlgorithm::Crc32c, None);
+        whole.update(contents);
+        assert_eq!(
+            whole.finish().parts,
+            vec![ChecksumAlgorithm::Crc32c.of_bytes(contents)]
+        );
+
+        let empty = PartChecksums::new(ChecksumAlgorithm::Md5, Some(4)).finish();
+        assert_eq!(empty.parts, vec![ChecksumAlgorithm::Md5.of_bytes(b"")]);
+    }
+
+    #[test]
+    fn part_count_suffix_is_ignored() {
+        assert!(s3_checksums_match("abc=-3", "abc="));
+        assert!(!s3_checksums_match("abc=-3", "abd=-3"));
+    }
+}
@@ -1,8 +1,12 @@
+use std::path::PathBuf;
+
 use aws_sdk_s3::operation::put_object::PutObjectError;
 use aws_smithy_http::{operation::Response, result::SdkError};
 use reqwest::{header::InvalidHeaderValue, StatusCode};
 use thiserror::Error;
 
+use super::checksum::ChecksumAlgorithm;
+
 #[derive(Error, Debug)]
 pub enum CliError {
     #[error("IO Error")]
@@ -14,16 +18,46 @@ pub enum CliError {
     Request(#[from] reqwest::Error),
     #[error("Header error")]
     InvalidHeader(#[from] InvalidHeaderValue),
-    #[error("API Auth Error")]
+    #[error("Couldn't read the token: {0}")]
+    InvalidToken(String),
+    #[error("No token for {0}, log in or set GIANT_TOKEN")]
+    NotLoggedIn(String),
+    #[error("Giant rejected the auth token, it may have expired. Log in again and rerun")]
     APIAuthError,
     #[error("Your current OS is not supported, please use Linux, MacOS, or Windows")]
     UnsupportedSystem,
+    #[error("Config error: {0}")]
+    Config(String),
     #[error("Input error: {0}")]
     InputError(String),
     #[error("Unexpected response from server: {0}")]
     UnexpectedResponse(StatusCode),
     #[error("Error while uploading to S3")]
     IngestionUploadError(#[from] Box<SdkError<PutObjectError, Response>>),
+    #[error("There's no collection called '{0}', or you don't have access to it")]
+    CollectionNotFound(String),
+    #[error("There's no ingestion called '{0}'")]
+    IngestionNotFound(String),
+    #[error("'{0}' already exists, pass --if-not-exists if that's fine")]
+    AlreadyExists(String),
+    #[error("Giant returned the same results for page {0} as the page before it, it may not support pagination")]
+    RepeatedPage(usize),
+    #[error("Can't resume from this progress log: {0}")]
+    JournalMismatch(String),
+    #[error("S3 reported checksum {returned} for {key}, expected {expected}")]
+    ChecksumMismatch {
+        key: String,
+        expected: String,
+        returned: String,
+    },
+    #[error(
+        "S3 didn't return a {0} checksum for {1}, it may not support them. Try --checksum md5"
+    )]
+    ChecksumMissing(ChecksumAlgorithm, String),
+    #[error("Couldn't write the log file {}: {1}", .0.display())]
+    LogFile(PathBuf, std::io::Error),
+    #[error("Interrupted before it finished")]
+    Interrupted,
     #[error("JSON error")]
     JsonError(#[from] serde_json::Error),
 }
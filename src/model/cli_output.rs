@@ -2,12 +2,13 @@ use std::error::Error;
 
 use clap::ValueEnum;
 use reflection::Reflection;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tsv::Config;
 
 use super::exit_code::FailureExitCode;
 
-#[derive(ValueEnum, Clone)]
+#[derive(ValueEnum, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
     Tsv,
     Json,
@@ -20,6 +21,38 @@ impl OutputFormat {
             Self::Tsv => "tsv",
         }
     }
+
+    /// Print a single item to stdout, exiting if it can't be serialized.
+    /// Used directly by commands which stream their results one row at a time.
+    pub fn print_item<T: Serialize + Reflection>(&self, item: &T) {
+        match self {
+            OutputFormat::Tsv => {
+                match Config::make_config(false, "()".into(), "TRUE".into(), "FALSE".into()) {
+                    Ok(config) => match tsv::to_string(item, config) {
+                        Ok(text) => println!("{text}"),
+                        Err(e) => {
+                            eprintln!("Failed to serialize output");
+                            eprintln!("{e}");
+                            std::process::exit(FailureExitCode::Serialization as i32);
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Invalid TSV output config, you'll need a new build of this tool to fix this");
+                        eprintln!("{e}");
+                        std::process::exit(FailureExitCode::Serialization as i32);
+                    }
+                }
+            }
+            OutputFormat::Json => match serde_json::to_string(item) {
+                Ok(text) => println!("{text}"),
+                Err(e) => {
+                    eprintln!("Failed to serialize output");
+                    eprintln!("{e}");
+                    std::process::exit(FailureExitCode::Serialization as i32);
+                }
+            },
+        }
+    }
 }
 
 pub struct CliResult<T: Serialize + Reflection, E: Error> {
@@ -44,33 +77,7 @@ impl<T: Serialize + Reflection, E: Error> CliResult<T, E> {
 
     pub fn print_or_exit(self, format: &OutputFormat) {
         match self.inner {
-            Ok(r) => match format {
-                OutputFormat::Tsv => {
-                    match Config::make_config(false, "()".into(), "TRUE".into(), "FALSE".into()) {
-                        Ok(config) => match tsv::to_string(&r, config) {
-                            Ok(text) => println!("{text}"),
-                            Err(e) => {
-                                eprintln!("Failed to serialize output");
-                                eprintln!("{e}");
-                                std::process::exit(FailureExitCode::Serialization as i32);
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!("Invalid TSV output config, you'll need a new build of this tool to fix this");
-                            eprintln!("{e}");
-                            std::process::exit(FailureExitCode::Serialization as i32);
-                        }
-                    }
-                }
-                OutputFormat::Json => match serde_json::to_string(&r) {
-                    Ok(text) => println!("{text}"),
-                    Err(e) => {
-                        eprintln!("Failed to serialize output");
-                        eprintln!("{e}");
-                        std::process::exit(FailureExitCode::Serialization as i32);
-                    }
-                },
-            },
+            Ok(r) => format.print_item(&r),
             Err(e) => {
                 eprintln!("{e}");
                 std::process::exit(self.exit_code as i32);
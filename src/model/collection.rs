@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use super::ingestion::Ingestion;
+use super::{ingestion::Ingestion, uri::Uri};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -10,3 +10,11 @@ pub struct Collection {
     pub ingestions: Vec<Ingestion>,
     pub created_by: Option<String>,
 }
+
+impl Collection {
+    pub fn ingestion(&self, ingestion_uri: &Uri) -> Option<&Ingestion> {
+        self.ingestions
+            .iter()
+            .find(|i| i.uri == ingestion_uri.as_str())
+    }
+}
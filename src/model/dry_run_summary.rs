@@ -0,0 +1,20 @@
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::Serialize;
+
+/// Printed at the end of a dry run, for scripts to pick up
+#[derive(Serialize, Reflection, Debug, Default)]
+pub struct DryRunSummary {
+    pub files: u64,
+    pub bytes: u64,
+    pub excluded_files: u64,
+    pub excluded_dirs: u64,
+    pub max_depth: u64,
+    pub uri_collisions: u64,
+    pub unreadable: u64,
+    // The counts below are only filled in with --skip-existing
+    pub checked_existing: bool,
+    pub already_in_giant: u64,
+    pub bytes_already_in_giant: u64,
+    pub failed_checks: u64,
+}
@@ -4,4 +4,14 @@ pub enum FailureExitCode {
     Api = 3,
     Serialization = 4,
     Upload = 5,
+    // The ingestion finished, but not every file was uploaded
+    PartialUpload = 6,
+    // The ingestion was stopped by Ctrl-C or SIGTERM before it finished
+    Interrupted = 7,
+    // Giant failed to process some of the ingestion's files
+    ProcessingFailed = 8,
+    // check-dir found files that Giant doesn't have, or that couldn't be checked
+    FilesMissing = 9,
+    // The config file or the profile picked from it is invalid
+    Config = 10,
 }
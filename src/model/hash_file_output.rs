@@ -13,3 +13,13 @@ impl HashFileOutput {
         HashFileOutput { hash, path }
     }
 }
+
+/// Used when hashing with --with-metadata, to build a manifest
+#[derive(Serialize, Reflection)]
+pub struct HashFileMetadataOutput {
+    pub hash: String,
+    pub path: String,
+    pub size: u64,
+    // RFC 3339, empty if the filesystem doesn't record it
+    pub modified: String,
+}
@@ -2,7 +2,6 @@ use std::path::Path;
 
 use chrono::{DateTime, Utc};
 use serde::Serialize;
-use walkdir::DirEntry;
 
 use super::uri::Uri;
 
@@ -22,10 +21,10 @@ impl IngestionFile {
     pub fn from_file(
         ingestion_uri: &Uri,
         base_path: impl AsRef<Path>,
-        e: &DirEntry,
+        path: &Path,
     ) -> anyhow::Result<IngestionFile> {
-        let metadata = e.metadata()?;
-        let relative_path = e.path().strip_prefix(base_path)?;
+        let metadata = path.metadata()?;
+        let relative_path = path.strip_prefix(base_path)?;
 
         let uri = ingestion_uri.extend_from_path(relative_path);
 
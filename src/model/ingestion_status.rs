@@ -0,0 +1,180 @@
+// Giant records the progress of every file through its extractors as ingestion events.
+// We only need the latest status of each extractor, so everything else is ignored and
+// anything missing is defaulted, to cope with older and newer Giants alike.
+
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::{Deserialize, Serialize};
+
+use super::ingestion::Ingestion;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobStatus {
+    #[serde(default)]
+    pub extractor_statuses: Vec<ExtractorStatus>,
+    #[serde(default)]
+    pub errors: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractorStatus {
+    #[serde(default)]
+    pub status_updates: Vec<StatusUpdate>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatusUpdate {
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingState {
+    Processing,
+    Complete,
+    Failed,
+}
+
+impl ProcessingState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingState::Processing => "processing",
+            ProcessingState::Complete => "complete",
+            ProcessingState::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Reflection, Debug)]
+pub struct IngestionStatus {
+    pub uri: String,
+    pub state: String,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub failure_message: Option<String>,
+    // Files Giant has started processing, those still waiting for a worker aren't counted
+    pub files: u64,
+    pub files_with_errors: u64,
+    pub extractors_in_progress: u64,
+    pub extractors_succeeded: u64,
+    pub extractors_failed: u64,
+}
+
+impl IngestionStatus {
+    pub fn new(ingestion: &Ingestion, blobs: &[BlobStatus]) -> Self {
+        let mut status = IngestionStatus {
+            uri: ingestion.uri.clone(),
+            state: String::new(),
+            start_time: ingestion.start_time.clone(),
+            end_time: ingestion.end_time.clone(),
+            failure_message: ingestion.failure_message.clone(),
+            files: blobs.len() as u64,
+            files_with_errors: 0,
+            extractors_in_progress: 0,
+            extractors_succeeded: 0,
+            extractors_failed: 0,
+        };
+
+        for blob in blobs {
+            if !blob.errors.is_empty() {
+                status.files_with_errors += 1;
+            }
+            for extractor in &blob.extractor_statuses {
+                let latest = extractor
+                    .status_updates
+                    .iter()
+                    .rev()
+                    .find_map(|update| update.status.as_deref());
+                match latest {
+                    Some("Success") => status.extractors_succeeded += 1,
+                    Some("Failure") => status.extractors_failed += 1,
+                    _ => status.extractors_in_progress += 1,
+                }
+            }
+        }
+
+        status.state = status.processing_state().as_str().to_owned();
+        status
+    }
+
+    pub fn processing_state(&self) -> ProcessingState {
+        if self.failure_message.is_some() {
+            ProcessingState::Failed
+        } else if self.extractors_in_progress > 0 {
+            ProcessingState::Processing
+        } else if self.files == 0 {
+            // Nothing picked up yet, unless the ingestion has ended without any files
+            match self.end_time {
+                Some(_) => ProcessingState::Complete,
+                None => ProcessingState::Processing,
+            }
+        } else if self.extractors_failed > 0 || self.files_with_errors > 0 {
+            ProcessingState::Failed
+        } else {
+            ProcessingState::Complete
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingestion(end_time: Option<&str>) -> Ingestion {
+        Ingestion {
+            display: "leak".into(),
+            uri: "collection/leak".into(),
+            start_time: "2024-03-01T12:00:00Z".into(),
+            end_time: end_time.map(|t| t.to_owned()),
+            path: None,
+            failure_message: None,
+            languages: vec![],
+            fixed: false,
+            default: false,
+        }
+    }
+
+    #[test]
+    fn counts_the_latest_status_of_each_extractor() {
+        let blobs: Vec<BlobStatus> = serde_json::from_str(
+            r#"[
+                {"extractorStatuses": [
+                    {"extractorType": "OcrMyPdf", "statusUpdates": [{"status": "Started"}, {"status": "Success"}]},
+                    {"extractorType": "Tika", "statusUpdates": [{"status": "Started"}]}
+                ]},
+                {"extractorStatuses": [
+                    {"extractorType": "Tika", "statusUpdates": [{"status": "Started"}, {"status": "Failure"}]}
+                ], "errors": [{"message": "Tika fell over"}], "somethingNew": true},
+                {}
+            ]"#,
+        )
+        .unwrap();
+        let status = IngestionStatus::new(&ingestion(None), &blobs);
+        assert_eq!(status.files, 3);
+        assert_eq!(status.files_with_errors, 1);
+        assert_eq!(
+            (
+                status.extractors_in_progress,
+                status.extractors_succeeded,
+                status.extractors_failed
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(status.processing_state(), ProcessingState::Processing);
+
+        let config =
+            tsv::Config::make_config(false, "()".into(), "TRUE".into(), "FALSE".into()).unwrap();
+        let row = tsv::to_string(&status, config).unwrap();
+        assert!(row.starts_with("collection/leak\tprocessing\t"), "{row}");
+    }
+
+    #[test]
+    fn still_processing_until_there_are_events_or_it_has_ended() {
+        let status = IngestionStatus::new(&ingestion(None), &[]);
+        assert_eq!(status.processing_state(), ProcessingState::Processing);
+
+        let status = IngestionStatus::new(&ingestion(Some("2024-03-01T12:05:00Z")), &[]);
+        assert_eq!(status.processing_state(), ProcessingState::Complete);
+    }
+}
@@ -0,0 +1,96 @@
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::Serialize;
+
+use super::exit_code::FailureExitCode;
+
+/// Printed at the end of an ingestion, for scripts to pick up
+#[derive(Serialize, Reflection, Debug, Default)]
+pub struct IngestionSummary {
+    pub success: u64,
+    pub failure: u64,
+    // Started but stopped part way by Ctrl-C or SIGTERM
+    pub interrupted: u64,
+    pub already_processed: u64,
+    pub skipped_existing: u64,
+    pub excluded_files: u64,
+    pub excluded_dirs: u64,
+    // Files and directories the walk couldn't read, so were never tried
+    pub unreadable: u64,
+    pub bytes_uploaded: u64,
+    pub bytes_skipped_existing: u64,
+    pub elapsed_secs: f64,
+    pub bytes_per_sec: f64,
+    pub stopped_early: bool,
+    pub log_path: String,
+}
+
+impl IngestionSummary {
+    pub fn failure_exit_code(&self) -> Option<FailureExitCode> {
+        if self.stopped_early {
+            Some(FailureExitCode::Interrupted)
+        } else if self.failure > 0 || self.unreadable > 0 {
+            Some(FailureExitCode::PartialUpload)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_reflects_failures_and_interruptions() {
+        let clean = IngestionSummary {
+            success: 3,
+            ..IngestionSummary::default()
+        };
+        assert!(clean.failure_exit_code().is_none());
+
+        let partial = IngestionSummary {
+            failure: 1,
+            ..IngestionSummary::default()
+        };
+        assert!(matches!(
+            partial.failure_exit_code(),
+            Some(FailureExitCode::PartialUpload)
+        ));
+
+        let unreadable = IngestionSummary {
+            success: 3,
+            unreadable: 1,
+            ..IngestionSummary::default()
+        };
+        assert!(matches!(
+            unreadable.failure_exit_code(),
+            Some(FailureExitCode::PartialUpload)
+        ));
+
+        let interrupted = IngestionSummary {
+            failure: 1,
+            stopped_early: true,
+            ..IngestionSummary::default()
+        };
+        assert!(matches!(
+            interrupted.failure_exit_code(),
+            Some(FailureExitCode::Interrupted)
+        ));
+    }
+
+    #[test]
+    fn serializes_as_a_single_tsv_row() {
+        let config =
+            tsv::Config::make_config(false, "()".into(), "TRUE".into(), "FALSE".into()).unwrap();
+        let summary = IngestionSummary {
+            success: 2,
+            elapsed_secs: 1.5,
+            log_path: "/tmp/log.tsv".into(),
+            ..IngestionSummary::default()
+        };
+        let row = tsv::to_string(&summary, config).unwrap();
+        assert_eq!(row.lines().count(), 1, "{row}");
+        assert!(row.ends_with("/tmp/log.tsv"), "{row}");
+    }
+}
@@ -0,0 +1,94 @@
+// The first line of every ingestion log, so that a log can be checked
+// against the ingestion it is being used to resume.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{cli_error::CliError, uri::Uri};
+
+/// Bump this whenever the columns or fields of the log change, or what they mean
+pub const JOURNAL_VERSION: u32 = 2;
+
+const TSV_MARKER: &str = "#giant-utils-journal";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct JournalHeader {
+    pub journal_version: u32,
+    pub tool_version: String,
+    pub ingestion_uri: String,
+    pub base_path: PathBuf,
+}
+
+impl JournalHeader {
+    pub fn new(ingestion_uri: &Uri, base_path: impl AsRef<Path>) -> Result<Self, CliError> {
+        Ok(JournalHeader {
+            journal_version: JOURNAL_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_owned(),
+            ingestion_uri: ingestion_uri.as_str().to_owned(),
+            // Store the absolute path so that resuming from another directory still matches
+            base_path: base_path.as_ref().canonicalize()?,
+        })
+    }
+
+    /// Check that a log written with this header can be used to resume the `expected` ingestion
+    pub fn check_resumable(&self, expected: &JournalHeader) -> Result<(), CliError> {
+        if self.journal_version > JOURNAL_VERSION {
+            Err(CliError::JournalMismatch(format!(
+                "log was written by giant-utils {} using journal version {}, this version only supports up to {}",
+                self.tool_version, self.journal_version, JOURNAL_VERSION
+            )))
+        } else if self.ingestion_uri != expected.ingestion_uri {
+            Err(CliError::JournalMismatch(format!(
+                "log is for ingestion '{}' not '{}'",
+                self.ingestion_uri, expected.ingestion_uri
+            )))
+        } else if self.base_path != expected.base_path {
+            Err(CliError::JournalMismatch(format!(
+                "log is for base path '{}' not '{}'",
+                self.base_path.display(),
+                expected.base_path.display()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = serde_json::to_string(self).unwrap();
+        s.push('\n');
+        s
+    }
+
+    pub fn to_tsv_row(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            TSV_MARKER,
+            self.journal_version,
+            self.tool_version,
+            self.ingestion_uri,
+            self.base_path.display()
+        )
+    }
+
+    /// Returns None if the line isn't a header, i.e. the log predates journal headers
+    pub fn from_json(line: &str) -> Option<Self> {
+        serde_json::from_str(line).ok()
+    }
+
+    /// Returns None if the line isn't a header, i.e. the log predates journal headers
+    pub fn from_tsv_row(line: &str) -> Option<Self> {
+        let mut cols = line.trim_end_matches('\n').split('\t');
+
+        if cols.next()? != TSV_MARKER {
+            return None;
+        }
+
+        Some(JournalHeader {
+            journal_version: cols.next()?.parse().ok()?,
+            tool_version: cols.next()?.to_owned(),
+            ingestion_uri: cols.next()?.to_owned(),
+            base_path: PathBuf::from(cols.next()?),
+        })
+    }
+}
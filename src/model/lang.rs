@@ -1,14 +1,60 @@
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-#[derive(ValueEnum, Clone, Debug, Serialize, Deserialize)]
+/// The languages Giant can OCR and extract text in. Each also accepts its
+/// ISO 639-1 and 639-2 codes on the command line.
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Language {
+    #[clap(aliases = ["ar", "ara"])]
     Arabic,
+    #[clap(aliases = ["zh", "zho", "chi"])]
+    Chinese,
+    #[clap(aliases = ["nl", "nld", "dut"])]
+    Dutch,
+    #[clap(aliases = ["en", "eng"])]
     English,
+    #[clap(aliases = ["fr", "fra", "fre"])]
     French,
+    #[clap(aliases = ["de", "deu", "ger"])]
     German,
-    Russian,
-    Portuguese,
+    #[clap(aliases = ["it", "ita"])]
+    Italian,
+    #[clap(aliases = ["fa", "fas", "per"])]
     Persian,
+    #[clap(aliases = ["pl", "pol"])]
+    Polish,
+    #[clap(aliases = ["pt", "por"])]
+    Portuguese,
+    #[clap(aliases = ["ru", "rus"])]
+    Russian,
+    #[clap(aliases = ["es", "spa"])]
+    Spanish,
+    #[clap(aliases = ["tr", "tur"])]
+    Turkish,
+    #[clap(aliases = ["uk", "ukr"])]
+    Ukrainian,
+}
+
+impl Language {
+    /// The name Giant and the command line use for this language
+    pub fn as_str(&self) -> &'static str {
+        self.to_possible_value()
+            .expect("every language has a name")
+            .get_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_and_iso_codes() {
+        for input in ["spanish", "Spanish", "es", "spa"] {
+            assert_eq!(Language::from_str(input, true), Ok(Language::Spanish));
+        }
+        assert_eq!(Language::from_str("zho", true), Ok(Language::Chinese));
+        assert!(Language::from_str("klingon", true).is_err());
+    }
 }
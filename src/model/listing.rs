@@ -0,0 +1,98 @@
+// Flattened views of collections and ingestions, one row each for list-collections and
+// list-ingestions, so that they print as a single TSV line.
+
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::Serialize;
+
+use super::{collection::Collection, ingestion::Ingestion};
+
+#[derive(Debug, Serialize, Reflection)]
+pub struct CollectionRow {
+    pub uri: String,
+    pub display: String,
+    pub created_by: Option<String>,
+    pub ingestions: u64,
+}
+
+impl From<&Collection> for CollectionRow {
+    fn from(collection: &Collection) -> Self {
+        CollectionRow {
+            uri: collection.uri.clone(),
+            display: collection.display.clone(),
+            created_by: collection.created_by.clone(),
+            ingestions: collection.ingestions.len() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Reflection)]
+pub struct IngestionRow {
+    pub uri: String,
+    pub display: String,
+    // Ingestions don't record who made them, so this is whoever created the collection
+    pub created_by: Option<String>,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub languages: String,
+    pub fixed: bool,
+    pub default: bool,
+    pub failure_message: Option<String>,
+}
+
+impl IngestionRow {
+    pub fn new(collection: &Collection, ingestion: &Ingestion) -> Self {
+        let languages = ingestion
+            .languages
+            .iter()
+            .map(|l| l.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        IngestionRow {
+            uri: ingestion.uri.clone(),
+            display: ingestion.display.clone(),
+            created_by: collection.created_by.clone(),
+            start_time: ingestion.start_time.clone(),
+            end_time: ingestion.end_time.clone(),
+            languages,
+            fixed: ingestion.fixed,
+            default: ingestion.default,
+            failure_message: ingestion.failure_message.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::lang::Language;
+
+    use super::*;
+
+    #[test]
+    fn ingestion_serializes_as_a_single_tsv_row() {
+        let ingestion = Ingestion {
+            display: "leak".into(),
+            uri: "collection/leak".into(),
+            start_time: "2024-03-01T12:00:00Z".into(),
+            end_time: None,
+            path: None,
+            failure_message: None,
+            languages: vec![Language::English, Language::Ukrainian],
+            fixed: false,
+            default: true,
+        };
+        let collection = Collection {
+            uri: "collection".into(),
+            display: "collection".into(),
+            ingestions: vec![],
+            created_by: Some("someone".into()),
+        };
+
+        let config =
+            tsv::Config::make_config(false, "()".into(), "TRUE".into(), "FALSE".into()).unwrap();
+        let row = tsv::to_string(&IngestionRow::new(&collection, &ingestion), config).unwrap();
+        assert_eq!(row.lines().count(), 1, "{row}");
+        assert!(row.contains("english,ukrainian"), "{row}");
+    }
+}
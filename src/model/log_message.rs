@@ -2,8 +2,11 @@ use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use super::{checksum::UploadChecksum, uri::Uri};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum FailureStage {
+    Hash,
     UploadData,
     UploadMetadata,
 }
@@ -12,19 +15,72 @@ pub enum FailureStage {
 #[serde(tag = "status")]
 pub enum LogMessage {
     Success {
+        // Absolute path on the local disk
         path: PathBuf,
+        // Path relative to the base of the ingestion
+        relative_path: PathBuf,
+        uri: Uri,
+        hash: String,
+        data_key: String,
+        metadata_key: String,
         size: u64,
-        start_millis: u128,
-        end_millis: u128,
+        start_millis: u64,
+        end_millis: u64,
+        // Total number of upload requests made for this file, across all stages
+        attempts: u32,
+        // What S3 verified the data against. Missing from logs written before
+        // checksums were recorded, and when a previous run uploaded the data.
+        #[serde(default)]
+        checksum: Option<UploadChecksum>,
     },
     Failure {
         path: PathBuf,
+        relative_path: PathBuf,
+        uri: Uri,
+        // Missing if hashing the file was what failed
+        hash: Option<String>,
+        data_key: String,
+        metadata_key: String,
         size: u64,
-        start_millis: u128,
-        end_millis: u128,
+        start_millis: u64,
+        end_millis: u64,
+        attempts: u32,
         failure_stage: FailureStage,
         reason: String,
     },
+    // Not uploaded because Giant already has a blob with this hash
+    SkippedExisting {
+        path: PathBuf,
+        relative_path: PathBuf,
+        uri: Uri,
+        hash: String,
+        size: u64,
+        start_millis: u64,
+        end_millis: u64,
+    },
+    // Uploaded by a previous run which this one resumed from,
+    // the details of that upload are in the previous run's log.
+    AlreadyProcessed {
+        path: PathBuf,
+        relative_path: PathBuf,
+        uri: Uri,
+        size: u64,
+    },
+    // Left out by --include, --exclude, a preset, a .giantignore or a size limit.
+    // Only logged with --log-excluded.
+    Excluded {
+        path: PathBuf,
+        relative_path: PathBuf,
+        is_dir: bool,
+        reason: String,
+    },
+    // A file or directory the walk couldn't read. Nothing is recorded for it on resume,
+    // so the next walk tries it again.
+    WalkFailure {
+        path: PathBuf,
+        relative_path: PathBuf,
+        reason: String,
+    },
 }
 
 impl LogMessage {
@@ -34,45 +90,143 @@ impl LogMessage {
         s
     }
 
+    // Columns are:
+    // status, path, relative_path, uri, hash, data_key, metadata_key,
+    // size, start_millis, end_millis, attempts, failure_stage, reason,
+    // checksum_algorithm, checksum, e_tag
     pub fn to_tsv_row(&self) -> String {
         match self {
             Self::Failure {
                 path,
+                relative_path,
+                uri,
+                hash,
+                data_key,
+                metadata_key,
                 size,
                 start_millis: start_epoch,
                 end_millis: end_epoch,
+                attempts,
                 failure_stage,
                 reason,
             } => {
                 let failure_stage = match failure_stage {
+                    FailureStage::Hash => "failed_to_hash",
                     FailureStage::UploadData => "failed_to_upload_data",
                     FailureStage::UploadMetadata => "failed_to_upload_metadata",
                 };
 
                 format!(
-                    "failure\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    "failure\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t\t\t\n",
                     path.display(),
+                    relative_path.display(),
+                    uri.as_str(),
+                    hash.as_deref().unwrap_or_default(),
+                    data_key,
+                    metadata_key,
                     size,
                     start_epoch,
                     end_epoch,
+                    attempts,
                     failure_stage,
-                    reason
+                    // Error messages can span lines, which would break the row
+                    reason.replace(['\t', '\n'], " ")
                 )
             }
             Self::Success {
                 path,
+                relative_path,
+                uri,
+                hash,
+                data_key,
+                metadata_key,
+                size,
+                start_millis: start_epoch,
+                end_millis: end_epoch,
+                attempts,
+                checksum,
+            } => {
+                let (checksum_algorithm, checksum, e_tag) = match checksum {
+                    Some(c) => (c.algorithm.as_str(), c.checksum.as_str(), c.e_tag.as_str()),
+                    None => ("", "", ""),
+                };
+
+                format!(
+                    "success\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t\t\t{}\t{}\t{}\n",
+                    path.display(),
+                    relative_path.display(),
+                    uri.as_str(),
+                    hash,
+                    data_key,
+                    metadata_key,
+                    size,
+                    start_epoch,
+                    end_epoch,
+                    attempts,
+                    checksum_algorithm,
+                    checksum,
+                    e_tag
+                )
+            }
+            Self::SkippedExisting {
+                path,
+                relative_path,
+                uri,
+                hash,
                 size,
                 start_millis: start_epoch,
                 end_millis: end_epoch,
             } => {
                 format!(
-                    "success\t{}\t{}\t{}\t{}\t\t\n",
+                    "skipped_existing\t{}\t{}\t{}\t{}\t\t\t{}\t{}\t{}\t\t\t\t\t\t\n",
                     path.display(),
+                    relative_path.display(),
+                    uri.as_str(),
+                    hash,
                     size,
                     start_epoch,
                     end_epoch
                 )
             }
+            Self::AlreadyProcessed {
+                path,
+                relative_path,
+                uri,
+                size,
+            } => {
+                format!(
+                    "already_processed\t{}\t{}\t{}\t\t\t\t{}\t\t\t\t\t\t\t\t\n",
+                    path.display(),
+                    relative_path.display(),
+                    uri.as_str(),
+                    size
+                )
+            }
+            Self::Excluded {
+                path,
+                relative_path,
+                is_dir: _,
+                reason,
+            } => {
+                format!(
+                    "excluded\t{}\t{}\t\t\t\t\t\t\t\t\t\t{}\t\t\t\n",
+                    path.display(),
+                    relative_path.display(),
+                    reason
+                )
+            }
+            Self::WalkFailure {
+                path,
+                relative_path,
+                reason,
+            } => {
+                format!(
+                    "walk_failure\t{}\t{}\t\t\t\t\t\t\t\t\t\t{}\t\t\t\n",
+                    path.display(),
+                    relative_path.display(),
+                    reason.replace(['\t', '\n'], " ")
+                )
+            }
         }
     }
 }
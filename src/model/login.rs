@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use humantime::format_duration;
+use reflection::Reflection;
+use reflection_derive::Reflection;
+use serde::{Deserialize, Serialize};
+
+use super::token_claims::TokenClaims;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Permissions {
+    #[serde(default)]
+    pub granted: Vec<String>,
+}
+
+/// One row of the logins command
+#[derive(Serialize, Reflection)]
+pub struct StoredLogin {
+    pub giant_uri: String,
+    pub username: Option<String>,
+    pub expires_at: Option<String>,
+    pub login_expires_at: Option<String>,
+}
+
+impl StoredLogin {
+    pub fn new(giant_uri: &str, claims: Option<&TokenClaims>) -> Self {
+        StoredLogin {
+            giant_uri: giant_uri.to_owned(),
+            username: claims.and_then(|c| c.username()).map(str::to_owned),
+            expires_at: claims.and_then(|c| c.expires_at()).map(|t| t.to_rfc3339()),
+            login_expires_at: claims
+                .and_then(|c| c.login_expires_at())
+                .map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Serialize, Reflection)]
+pub struct WhoAmI {
+    pub giant_uri: String,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub permissions: String,
+    pub issued_at: Option<String>,
+    pub expires_at: Option<String>,
+    // How long an ingestion started now has before the token needs refreshing
+    pub expires_in: Option<String>,
+    pub login_expires_at: Option<String>,
+    pub login_expires_in: Option<String>,
+}
+
+impl WhoAmI {
+    /// Without claims if the token isn't a JWT we can read, since Giant may still accept it
+    pub fn new(giant_uri: &str, claims: Option<&TokenClaims>, permissions: Permissions) -> Self {
+        let now = Utc::now();
+        let expires_at = claims.and_then(|c| c.expires_at());
+        let login_expires_at = claims.and_then(|c| c.login_expires_at());
+        WhoAmI {
+            giant_uri: giant_uri.to_owned(),
+            username: claims.and_then(|c| c.username()).map(str::to_owned),
+            display_name: claims
+                .and_then(|c| c.user.as_ref())
+                .and_then(|user| user.display_name.clone()),
+            permissions: permissions.granted.join(","),
+            issued_at: claims.and_then(|c| c.issued_at()).map(|t| t.to_rfc3339()),
+            expires_at: expires_at.map(|t| t.to_rfc3339()),
+            expires_in: expires_at.map(|t| time_until(now, t)),
+            login_expires_at: login_expires_at.map(|t| t.to_rfc3339()),
+            login_expires_in: login_expires_at.map(|t| time_until(now, t)),
+        }
+    }
+}
+
+fn time_until(now: DateTime<Utc>, then: DateTime<Utc>) -> String {
+    match (then - now).to_std() {
+        Ok(duration) => {
+            format_duration(std::time::Duration::from_secs(duration.as_secs())).to_string()
+        }
+        Err(_) => "expired".to_owned(),
+    }
+}
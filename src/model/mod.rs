@@ -1,13 +1,23 @@
 pub mod blob;
+pub mod check_dir_summary;
+pub mod checked_file;
+pub mod checksum;
 pub mod cli_error;
 pub mod cli_output;
 pub mod collection;
+pub mod dry_run_summary;
 pub mod exit_code;
 pub mod file_metadata;
 pub mod forms;
 pub mod hash_file_output;
 pub mod ingestion;
 pub mod ingestion_file;
+pub mod ingestion_status;
+pub mod ingestion_summary;
+pub mod journal_header;
 pub mod lang;
+pub mod listing;
 pub mod log_message;
+pub mod login;
+pub mod token_claims;
 pub mod uri;
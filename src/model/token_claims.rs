@@ -0,0 +1,109 @@
+// What Giant puts in its auth tokens, which are JWTs. We only read the claims to report
+// on them and never verify the signature, that's for Giant to do.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use super::cli_error::CliError;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUser {
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenClaims {
+    pub user: Option<TokenUser>,
+    pub sub: Option<String>,
+    // Standard JWT claims, in seconds
+    pub iat: Option<i64>,
+    pub exp: Option<i64>,
+    // Giant's own, in milliseconds. The token has to be refreshed before verification
+    // expiry, which can be done until login expiry after which you have to log in again.
+    pub issued_at: Option<i64>,
+    pub login_expiry: Option<i64>,
+    pub verification_expiry: Option<i64>,
+}
+
+impl TokenClaims {
+    /// Accepts the token with or without its "Bearer " prefix
+    pub fn decode(token: &str) -> Result<Self, CliError> {
+        let token = token.trim();
+        let jwt = token.strip_prefix("Bearer ").unwrap_or(token);
+        let payload = jwt
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| CliError::InvalidToken("it isn't a JWT".to_owned()))?;
+        let json = base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+            .map_err(|e| CliError::InvalidToken(e.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| CliError::InvalidToken(e.to_string()))
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.user
+            .as_ref()
+            .and_then(|user| user.username.as_deref())
+            .or(self.sub.as_deref())
+    }
+
+    pub fn issued_at(&self) -> Option<DateTime<Utc>> {
+        self.issued_at
+            .and_then(from_millis)
+            .or_else(|| self.iat.and_then(from_secs))
+    }
+
+    /// When the token stops working unless it's refreshed
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.verification_expiry
+            .and_then(from_millis)
+            .or_else(|| self.exp.and_then(from_secs))
+    }
+
+    /// When you'll have to log in again, however often the token is refreshed
+    pub fn login_expires_at(&self) -> Option<DateTime<Utc>> {
+        self.login_expiry
+            .and_then(from_millis)
+            .or_else(|| self.exp.and_then(from_secs))
+    }
+}
+
+fn from_secs(secs: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(secs, 0).single()
+}
+
+fn from_millis(millis: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(millis).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_giant_claims_from_a_bearer_token() {
+        let claims = r#"{"user":{"username":"jbloggs","displayName":"Joe Bloggs"},"issuedAt":1700000000000,"loginExpiry":1700086400000,"verificationExpiry":1700003600000}"#;
+        let token = format!(
+            "Bearer eyJhbGciOiJIUzI1NiJ9.{}.c2lnbmF0dXJl",
+            base64::encode_config(claims, base64::URL_SAFE_NO_PAD)
+        );
+
+        let claims = TokenClaims::decode(&token).unwrap();
+        assert_eq!(claims.username(), Some("jbloggs"));
+        assert_eq!(
+            claims.expires_at().unwrap().to_rfc3339(),
+            "2023-11-14T23:13:20+00:00"
+        );
+        assert_eq!(
+            claims.login_expires_at().unwrap().to_rfc3339(),
+            "2023-11-15T22:13:20+00:00"
+        );
+
+        assert!(matches!(
+            TokenClaims::decode("not-a-jwt"),
+            Err(CliError::InvalidToken(_))
+        ));
+    }
+}
@@ -2,9 +2,9 @@ use std::path::Path;
 
 use super::cli_error::CliError;
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Uri(String);
 
 impl Uri {
@@ -54,6 +54,7 @@ impl From<&str> for Uri {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_pattern_matching)]
 mod tests {
     use super::*;
 
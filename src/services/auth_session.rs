@@ -0,0 +1,120 @@
+//! The token the Giant API client is currently using, and when it needs refreshing
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::{header::HeaderMap, Client, Url};
+
+use crate::{
+    auth_store,
+    model::{cli_error::CliError, token_claims::TokenClaims},
+};
+
+/// How long before the token expires that we start trying to refresh it
+const REFRESH_MARGIN_MINUTES: i64 = 5;
+
+pub struct AuthSession {
+    pub client: Client,
+    /// Bumped every time the token changes, so that requests which failed
+    /// with an old token can tell someone has already replaced it
+    pub generation: u64,
+    expires_at: Option<DateTime<Utc>>,
+    login_expires_at: Option<DateTime<Utc>>,
+    /// Giant didn't offer a new token last time we asked, so there's no point asking again
+    refresh_declined: bool,
+}
+
+impl AuthSession {
+    pub fn new(token: &str, generation: u64) -> Result<Self, CliError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", token.parse()?);
+        let client = Client::builder().default_headers(headers).build()?;
+        // Not every token is a JWT we can read, those are just never refreshed early
+        let claims = TokenClaims::decode(token).unwrap_or_default();
+
+        Ok(AuthSession {
+            client,
+            generation,
+            expires_at: claims.expires_at(),
+            login_expires_at: claims.login_expires_at(),
+            refresh_declined: false,
+        })
+    }
+
+    pub fn needs_refresh(&self, now: DateTime<Utc>) -> bool {
+        let expiring = self
+            .expires_at
+            .is_some_and(|expires_at| expires_at - now < Duration::minutes(REFRESH_MARGIN_MINUTES));
+        // Past login expiry only logging in again will do
+        let refreshable = self
+            .login_expires_at
+            .is_none_or(|login_expires_at| login_expires_at > now);
+
+        expiring && refreshable && !self.refresh_declined
+    }
+
+    pub fn decline_refresh(&mut self) {
+        if !self.refresh_declined {
+            self.refresh_declined = true;
+            match self.expires_at {
+                Some(expires_at) => eprintln!(
+                    "Giant didn't refresh the auth token, it expires at {}",
+                    expires_at.to_rfc3339()
+                ),
+                None => eprintln!("Giant didn't refresh the auth token"),
+            }
+        }
+    }
+}
+
+/// Ask whoever is at the terminal for a new token, blocking until they give one.
+/// Returns None if there's nobody to ask, or they'd rather give up.
+pub fn prompt_for_token(base_url: &Url) -> Option<String> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+
+    eprint!(
+        "Giant at {base_url} rejected the auth token, it may have expired. \
+        Log in again and paste the new token from the about page to carry on, \
+        or press Enter to give up: "
+    );
+    io::stderr().flush().ok()?;
+
+    let mut token = String::new();
+    io::stdin().lock().read_line(&mut token).ok()?;
+    auth_store::non_empty(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn session(expires_at: DateTime<Utc>, login_expires_at: DateTime<Utc>) -> AuthSession {
+        AuthSession {
+            client: Client::new(),
+            generation: 0,
+            expires_at: Some(expires_at),
+            login_expires_at: Some(login_expires_at),
+            refresh_declined: false,
+        }
+    }
+
+    #[test]
+    fn refreshes_shortly_before_expiry_until_login_expires() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+
+        let fresh = session(now + Duration::hours(1), now + Duration::days(1));
+        assert!(!fresh.needs_refresh(now));
+
+        let mut expiring = session(now + Duration::minutes(2), now + Duration::days(1));
+        assert!(expiring.needs_refresh(now));
+        expiring.decline_refresh();
+        assert!(!expiring.needs_refresh(now));
+
+        let logged_out = session(now - Duration::minutes(2), now - Duration::minutes(1));
+        assert!(!logged_out.needs_refresh(now));
+    }
+}
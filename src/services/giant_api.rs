@@ -1,63 +1,277 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::RwLock};
 
+use chrono::Utc;
 use clap::ValueEnum;
-use reqwest::{header::HeaderMap, Client, Error, StatusCode, Url};
+use futures::{stream, Stream, TryStreamExt};
+use humantime::format_duration;
+use indicatif::ProgressBar;
+use reqwest::{Client, Error, Method, StatusCode, Url};
 use reqwest::{RequestBuilder, Response};
 
+use super::{
+    auth_session::{prompt_for_token, AuthSession},
+    retry::RetryPolicy,
+};
+
 use crate::model::blob::{Blob, BlobResp};
 use crate::{
-    auth_store::{self},
+    auth_store::{self, AuthToken},
     model::{
+        checked_file::HashStatus,
         cli_error::CliError,
         collection::Collection,
         forms::{create_collection::CreateCollection, create_ingestion::CreateIngestion},
+        ingestion_status::BlobStatus,
         lang::Language,
+        login::Permissions,
         uri::Uri,
     },
 };
 
+const BLOBS_PAGE_SIZE: usize = 500;
+
+/// How many new tokens a single request can be retried with before the 401 is returned
+const MAX_REAUTHENTICATIONS: u32 = 3;
+
 #[derive(ValueEnum, Clone)]
 pub enum ListBlobsFilter {
     All,
     InMultiple,
 }
 
+struct BlobPageState<'a> {
+    client: &'a GiantApiClient,
+    page: usize,
+    previous_first_uri: Option<String>,
+    finished: bool,
+}
+
 pub struct GiantApiClient {
-    client: Client,
+    // Swapped out whenever the token is refreshed
+    session: RwLock<AuthSession>,
+    // Held while the token is being replaced, so that requests running
+    // at the same time wait for one refresh rather than each doing their own
+    refresh_lock: tokio::sync::Mutex<()>,
     base_url: Url,
+    retry_policy: RetryPolicy,
+    // Tokens passed in with GIANT_TOKEN or --token-file are never saved
+    save_refreshed_tokens: bool,
+    // Hidden while asking for a new token, so that it isn't drawn over the prompt
+    progress_bar: RwLock<Option<ProgressBar>>,
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
 }
 
 impl GiantApiClient {
-    pub fn new(base_url: Url) -> Self {
-        let auth_token = auth_store::get(base_url.as_str()).unwrap();
-        let mut headers = HeaderMap::new();
-        headers.insert("Authorization", auth_token.parse().unwrap());
-        let client = Client::builder().default_headers(headers).build().unwrap();
-        Self { client, base_url }
-    }
-
-    async fn send_request(&mut self, request_builder: RequestBuilder) -> Result<Response, Error> {
-        let resp = request_builder.send().await?;
-        let auth_response_header = resp.headers().get("X-Offer-Authorization");
-
-        match auth_response_header {
-            Some(token_header_value) => {
-                let token = token_header_value
-                    .to_str()
-                    .expect("X-Offer-Authorization should contain only ASCII chars");
-                println!("Giant API returned new token in X-Offer-Authorization header. Refreshing client and auth store");
-                auth_store::set(self.base_url.as_str(), token).unwrap();
-                let mut headers = HeaderMap::new();
-                headers.insert("Authorization", token_header_value.clone());
-                self.client = Client::builder().default_headers(headers).build().unwrap();
+    pub fn new(
+        base_url: Url,
+        auth_token: AuthToken,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, CliError> {
+        Ok(Self {
+            session: RwLock::new(AuthSession::new(&auth_token.token, 0)?),
+            refresh_lock: tokio::sync::Mutex::new(()),
+            base_url,
+            retry_policy,
+            save_refreshed_tokens: auth_token.stored,
+            progress_bar: RwLock::new(None),
+        })
+    }
+
+    /// The progress bar being drawn while this client is in use, if any
+    pub fn set_progress_bar(&self, pb: &ProgressBar) {
+        *self.progress_bar.write().unwrap() = Some(pb.clone());
+    }
+
+    async fn send_request(&self, request_builder: RequestBuilder) -> Result<Response, Error> {
+        let mut request = request_builder.build()?;
+        // Anything that isn't idempotent (e.g. creating a collection) is only
+        // retried if we know the server didn't act on the previous attempt.
+        let idempotent = is_idempotent(request.method());
+        let mut attempt = 1;
+        let mut reauthentications = 0;
+
+        self.refresh_if_expiring().await;
+
+        loop {
+            // Requests with streaming bodies can't be cloned, so only get one attempt
+            let next_request = request.try_clone();
+            let can_retry = next_request.is_some() && attempt < self.retry_policy.max_attempts;
+            let (client, generation) = self.current_session();
+
+            let (delay, reason) = match client.execute(request).await {
+                Ok(resp) if resp.status() == StatusCode::UNAUTHORIZED => {
+                    // Giant never acts on a request it doesn't accept the token for,
+                    // so once there's a new one it can be sent again whatever it was
+                    // but not forever, if each new token is rejected too
+                    if reauthentications == MAX_REAUTHENTICATIONS {
+                        return Ok(resp);
+                    }
+                    match next_request {
+                        Some(next_request) if self.reauthenticate(generation).await => {
+                            request = next_request;
+                            reauthentications += 1;
+                            continue;
+                        }
+                        _ => return Ok(resp),
+                    }
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let retryable = self.retry_policy.is_retryable_status(status)
+                        && (idempotent || status == StatusCode::TOO_MANY_REQUESTS);
+
+                    if !retryable || !can_retry {
+                        self.accept_offered_token(&resp);
+                        return Ok(resp);
+                    }
+
+                    let delay = self
+                        .retry_policy
+                        .delay_after_response(attempt, resp.headers());
+                    (delay, status.to_string())
+                }
+                Err(e) => {
+                    let retryable =
+                        self.retry_policy.is_retryable_error(&e) && (idempotent || e.is_connect());
+
+                    if !retryable || !can_retry {
+                        return Err(e);
+                    }
+
+                    (self.retry_policy.backoff(attempt), e.to_string())
+                }
+            };
+
+            eprintln!(
+                "Giant API request failed ({reason}), retrying in {} (attempt {attempt} of {})",
+                format_duration(delay),
+                self.retry_policy.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+
+            match next_request {
+                Some(next_request) => request = next_request,
+                None => unreachable!("Only retrying requests which could be cloned"),
             }
-            None => println!("No X-Offer-Authorization header in response from Giant API"),
+            attempt += 1;
         }
+    }
+
+    fn http(&self) -> Client {
+        self.current_session().0
+    }
 
-        Ok(resp)
+    fn current_session(&self) -> (Client, u64) {
+        let session = self.session.read().unwrap();
+        // Cheap, the underlying connection pool is reference counted
+        (session.client.clone(), session.generation)
     }
 
-    pub async fn check_hash_exists(&mut self, hash: &str) -> Result<bool, CliError> {
+    /// Giant offers a new token on any request made with one that's close to expiring,
+    /// so ask for one with a keepalive rather than waiting for the token to stop working
+    async fn refresh_if_expiring(&self) {
+        if !self.session.read().unwrap().needs_refresh(Utc::now()) {
+            return;
+        }
+
+        let _refreshing = self.refresh_lock.lock().await;
+        // Whoever had the lock before us may have just refreshed it
+        if !self.session.read().unwrap().needs_refresh(Utc::now()) {
+            return;
+        }
+
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("keepalive");
+
+        let refreshed = match self.http().get(url).send().await {
+            Ok(resp) => self.accept_offered_token(&resp),
+            Err(e) => {
+                eprintln!("Couldn't refresh the Giant auth token: {e}");
+                false
+            }
+        };
+        if !refreshed {
+            self.session.write().unwrap().decline_refresh();
+        }
+    }
+
+    /// After a 401, wait for the user to log in again rather than failing the whole
+    /// ingestion or listing that the request was part of. Returns whether there's a
+    /// new token to try, which there can't be if there's nobody at a terminal to ask.
+    async fn reauthenticate(&self, failed_generation: u64) -> bool {
+        let _refreshing = self.refresh_lock.lock().await;
+        // Another request was rejected too and has already been given a new token
+        if self.session.read().unwrap().generation != failed_generation {
+            return true;
+        }
+
+        let base_url = self.base_url.clone();
+        let progress_bar = self.progress_bar.read().unwrap().clone();
+        let token = tokio::task::spawn_blocking(move || match progress_bar {
+            Some(pb) => pb.suspend(|| prompt_for_token(&base_url)),
+            None => prompt_for_token(&base_url),
+        })
+        .await
+        .ok()
+        .flatten();
+
+        match token {
+            Some(token) => match self.use_token(&token) {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Couldn't use that token: {e}");
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    /// Returns whether the response offered a new token
+    fn accept_offered_token(&self, resp: &Response) -> bool {
+        // Most responses won't offer a new token, and logging that for
+        // every request drowns out everything else during an ingestion
+        let token = match resp.headers().get("X-Offer-Authorization") {
+            Some(token) => token
+                .to_str()
+                .expect("X-Offer-Authorization should contain only ASCII chars"),
+            None => return false,
+        };
+
+        eprintln!("Giant API returned new token in X-Offer-Authorization header. Refreshing client and auth store");
+        match self.use_token(token) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Couldn't use the refreshed token: {e}");
+                false
+            }
+        }
+    }
+
+    fn use_token(&self, token: &str) -> Result<(), CliError> {
+        let mut session = self.session.write().unwrap();
+        *session = AuthSession::new(token, session.generation + 1)?;
+        drop(session);
+
+        if self.save_refreshed_tokens {
+            // The token in memory is all the rest of this run needs
+            if let Err(e) = auth_store::set(self.base_url.as_str(), token) {
+                eprintln!("Couldn't save the refreshed token, you may need to log in again next time: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn check_hash_exists(&self, hash: &str) -> Result<bool, CliError> {
         let mut url = self.base_url.clone();
 
         url.path_segments_mut()
@@ -68,7 +282,7 @@ impl GiantApiClient {
 
         url.query_pairs_mut().append_pair("basic", "true");
 
-        let res = self.send_request(self.client.get(url)).await?;
+        let res = self.send_request(self.http().get(url)).await?;
         let status = res.status();
 
         if status == 401 {
@@ -78,102 +292,199 @@ impl GiantApiClient {
         }
     }
 
-    pub async fn get_or_insert_collection(
-        &mut self,
-        ingestion_uri: &Uri,
-    ) -> Result<Collection, CliError> {
-        let collection = ingestion_uri.collection();
+    /// Unlike check_hash_exists, tells apart files Giant doesn't have from those you can't see
+    pub async fn get_hash_status(&self, hash: &str) -> Result<HashStatus, CliError> {
+        let mut url = self.base_url.clone();
 
-        let mut collections_url = self.base_url.clone();
-        collections_url
-            .path_segments_mut()
+        url.path_segments_mut()
             .unwrap()
             .push("api")
-            .push("collections");
+            .push("resources")
+            .push(hash);
 
-        let mut collection_url = collections_url.clone();
-        collection_url.path_segments_mut().unwrap().push(collection);
+        url.query_pairs_mut().append_pair("basic", "true");
 
-        let res = self.send_request(self.client.get(collection_url)).await?;
+        let res = self.send_request(self.http().get(url)).await?;
+        hash_status(res.status())
+    }
+
+    pub async fn get_current_user_permissions(&self) -> Result<Permissions, CliError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("currentUser")
+            .push("permissions");
+
+        let res = self.send_request(self.http().get(url)).await?;
         let status = res.status();
 
-        if status == StatusCode::UNAUTHORIZED {
-            return Err(CliError::APIAuthError);
+        if status == StatusCode::OK {
+            Ok(res.json::<Permissions>().await?)
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err(CliError::APIAuthError)
+        } else {
+            Err(CliError::UnexpectedResponse(status))
         }
+    }
 
-        if status == StatusCode::NOT_FOUND {
-            // Insert collection
-            let create_collection = CreateCollection {
-                name: collection.to_owned(),
-            };
-            let res = self
-                .send_request(self.client.post(collections_url).json(&create_collection))
-                .await?;
-            let status = res.status();
+    pub async fn list_collections(&self) -> Result<Vec<Collection>, CliError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("collections");
 
-            if status == StatusCode::UNAUTHORIZED {
-                return Err(CliError::APIAuthError);
-            } else if status != StatusCode::CREATED {
-                return Err(CliError::UnexpectedResponse(status));
-            }
+        let res = self.send_request(self.http().get(url)).await?;
+        let status = res.status();
+
+        if status == StatusCode::OK {
+            Ok(res.json::<Vec<Collection>>().await?)
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err(CliError::APIAuthError)
+        } else {
+            Err(CliError::UnexpectedResponse(status))
+        }
+    }
+
+    pub async fn get_collection(&self, collection: &str) -> Result<Collection, CliError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("collections")
+            .push(collection);
+
+        let res = self.send_request(self.http().get(url)).await?;
+        let status = res.status();
+
+        if status == StatusCode::OK {
             Ok(res.json::<Collection>().await?)
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err(CliError::APIAuthError)
+        } else if status == StatusCode::NOT_FOUND {
+            Err(CliError::CollectionNotFound(collection.to_owned()))
         } else {
+            Err(CliError::UnexpectedResponse(status))
+        }
+    }
+
+    /// The progress of each file in the ingestion through Giant's extractors
+    pub async fn get_ingestion_events(
+        &self,
+        ingestion_uri: &Uri,
+    ) -> Result<Vec<BlobStatus>, CliError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("ingestion-events")
+            .push(ingestion_uri.collection())
+            .push(ingestion_uri.ingestion());
+
+        let res = self.send_request(self.http().get(url)).await?;
+        let status = res.status();
+
+        if status == StatusCode::OK {
+            Ok(res.json::<Vec<BlobStatus>>().await?)
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err(CliError::APIAuthError)
+        } else {
+            Err(CliError::UnexpectedResponse(status))
+        }
+    }
+
+    pub async fn create_collection(&self, collection: &str) -> Result<Collection, CliError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("collections");
+
+        let create_collection = CreateCollection {
+            name: collection.to_owned(),
+        };
+        let res = self
+            .send_request(self.http().post(url).json(&create_collection))
+            .await?;
+        let status = res.status();
+
+        if status == StatusCode::CREATED {
             Ok(res.json::<Collection>().await?)
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err(CliError::APIAuthError)
+        } else {
+            Err(CliError::UnexpectedResponse(status))
+        }
+    }
+
+    pub async fn create_ingestion(
+        &self,
+        ingestion_uri: &Uri,
+        create_ingestion: &CreateIngestion,
+    ) -> Result<(), CliError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .push("api")
+            .push("collections")
+            .push(ingestion_uri.collection());
+
+        let res = self
+            .send_request(self.http().post(url).json(create_ingestion))
+            .await?;
+        let status = res.status();
+
+        if status == StatusCode::OK {
+            Ok(())
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err(CliError::APIAuthError)
+        } else {
+            Err(CliError::UnexpectedResponse(status))
+        }
+    }
+
+    pub async fn get_or_insert_collection(
+        &self,
+        ingestion_uri: &Uri,
+    ) -> Result<Collection, CliError> {
+        match self.get_collection(ingestion_uri.collection()).await {
+            Err(CliError::CollectionNotFound(collection)) => {
+                self.create_collection(&collection).await
+            }
+            result => result,
         }
     }
 
     pub async fn get_or_insert_ingestion(
-        &mut self,
+        &self,
         ingestion_uri: &Uri,
         base_collection: &Collection,
         path: PathBuf,
         languages: Vec<Language>,
     ) -> Result<(), CliError> {
-        let mut url = self.base_url.clone();
-
-        let collection = ingestion_uri.collection();
-        let ingestion = ingestion_uri.ingestion();
-
-        if base_collection
-            .ingestions
-            .iter()
-            .any(|i| i.uri == ingestion_uri.as_str())
-        {
+        if base_collection.ingestion(ingestion_uri).is_some() {
             // collection already contains ingestion!
             Ok(())
         } else {
-            url.path_segments_mut()
-                .unwrap()
-                .push("api")
-                .push("collections")
-                .push(collection);
-
             let create_ingestion = CreateIngestion {
                 path: Some(path),
-                name: Some(ingestion.to_owned()),
+                name: Some(ingestion_uri.ingestion().to_owned()),
                 languages,
-                fixed: Some(false), // This is hardcoded to false in the existing CLI
+                fixed: Some(false),
                 default: Some(false),
             };
-
-            let res = self
-                .send_request(self.client.post(url).json(&create_ingestion))
-                .await?;
-            let status = res.status();
-
-            if status == StatusCode::OK {
-                Ok(())
-            } else {
-                Err(CliError::UnexpectedResponse(status))
-            }
+            self.create_ingestion(ingestion_uri, &create_ingestion)
+                .await
         }
     }
 
-    // Returns a maximum of 500 blobs per request
+    // Returns a single page of at most BLOBS_PAGE_SIZE blobs, pages are numbered from 1
     pub async fn get_blobs_in_collection(
-        &mut self,
+        &self,
         collection: &str,
         filter: &ListBlobsFilter,
+        page: usize,
     ) -> Result<Vec<Blob>, CliError> {
         let mut url = self.base_url.clone();
         url.path_segments_mut().unwrap().push("api").push("blobs");
@@ -183,10 +494,13 @@ impl GiantApiClient {
             ListBlobsFilter::All => "false",
         };
 
-        url.query_pairs_mut().append_pair("inMultiple", in_multiple);
-        url.query_pairs_mut().append_pair("collection", collection);
+        url.query_pairs_mut()
+            .append_pair("inMultiple", in_multiple)
+            .append_pair("collection", collection)
+            .append_pair("page", &page.to_string())
+            .append_pair("pageSize", &BLOBS_PAGE_SIZE.to_string());
 
-        let res = self.send_request(self.client.get(url)).await?;
+        let res = self.send_request(self.http().get(url)).await?;
         let status = res.status();
 
         if status == StatusCode::OK {
@@ -197,7 +511,54 @@ impl GiantApiClient {
         }
     }
 
-    pub async fn delete_blob(&mut self, blob_uri: &str) -> Result<(), CliError> {
+    /// Stream every blob in a collection, requesting the next page from Giant
+    /// only once the previous one has been consumed.
+    pub fn stream_blobs_in_collection<'a>(
+        &'a self,
+        collection: &'a str,
+        filter: &'a ListBlobsFilter,
+    ) -> impl Stream<Item = Result<Blob, CliError>> + 'a {
+        let initial_state = BlobPageState {
+            client: self,
+            page: 1,
+            previous_first_uri: None,
+            finished: false,
+        };
+
+        stream::try_unfold(initial_state, move |state| async move {
+            if state.finished {
+                return Ok(None);
+            }
+
+            let blobs = state
+                .client
+                .get_blobs_in_collection(collection, filter, state.page)
+                .await?;
+
+            let first_uri = match blobs.first() {
+                Some(blob) => blob.uri.clone(),
+                None => return Ok(None),
+            };
+
+            // An older Giant which ignores the page parameter would hand us
+            // the first page forever, so bail rather than loop.
+            if state.previous_first_uri.as_ref() == Some(&first_uri) {
+                return Err(CliError::RepeatedPage(state.page));
+            }
+
+            let next_state = BlobPageState {
+                finished: blobs.len() < BLOBS_PAGE_SIZE,
+                client: state.client,
+                page: state.page + 1,
+                previous_first_uri: Some(first_uri),
+            };
+
+            Ok(Some((stream::iter(blobs.into_iter().map(Ok)), next_state)))
+        })
+        .try_flatten()
+    }
+
+    pub async fn delete_blob(&self, blob_uri: &str) -> Result<(), CliError> {
         let mut url = self.base_url.clone();
         url.path_segments_mut()
             .unwrap()
@@ -209,7 +570,7 @@ impl GiantApiClient {
         // children (i.e. because they're archives and contain further files).
         url.query_pairs_mut().append_pair("checkChildren", "false");
 
-        let res = self.send_request(self.client.delete(url)).await?;
+        let res = self.send_request(self.http().delete(url)).await?;
 
         let status = res.status();
 
@@ -220,7 +581,7 @@ impl GiantApiClient {
         }
     }
 
-    pub async fn delete_collection(&mut self, collection: &str) -> Result<(), CliError> {
+    pub async fn delete_collection(&self, collection: &str) -> Result<(), CliError> {
         let mut url = self.base_url.clone();
         url.path_segments_mut()
             .unwrap()
@@ -228,7 +589,7 @@ impl GiantApiClient {
             .push("collections")
             .push(collection);
 
-        let res = self.send_request(self.client.delete(url)).await?;
+        let res = self.send_request(self.http().delete(url)).await?;
         let status = res.status();
 
         if status == StatusCode::NO_CONTENT {
@@ -238,3 +599,39 @@ impl GiantApiClient {
         }
     }
 }
+
+fn hash_status(status: StatusCode) -> Result<HashStatus, CliError> {
+    match status {
+        StatusCode::OK => Ok(HashStatus::Present),
+        StatusCode::NOT_FOUND => Ok(HashStatus::Missing),
+        StatusCode::FORBIDDEN => Ok(HashStatus::Forbidden),
+        StatusCode::UNAUTHORIZED => Err(CliError::APIAuthError),
+        _ => Err(CliError::UnexpectedResponse(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_apart_missing_and_forbidden_hashes() {
+        assert_eq!(hash_status(StatusCode::OK).unwrap(), HashStatus::Present);
+        assert_eq!(
+            hash_status(StatusCode::NOT_FOUND).unwrap(),
+            HashStatus::Missing
+        );
+        assert_eq!(
+            hash_status(StatusCode::FORBIDDEN).unwrap().as_str(),
+            "forbidden"
+        );
+        assert!(matches!(
+            hash_status(StatusCode::UNAUTHORIZED),
+            Err(CliError::APIAuthError)
+        ));
+        assert!(matches!(
+            hash_status(StatusCode::BAD_GATEWAY),
+            Err(CliError::UnexpectedResponse(StatusCode::BAD_GATEWAY))
+        ));
+    }
+}
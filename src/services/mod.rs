@@ -1,3 +1,5 @@
+pub mod auth_session;
 mod aws;
 pub mod giant_api;
+pub mod retry;
 pub mod s3_client;
@@ -0,0 +1,187 @@
+//! Retry policy shared by everything that talks to a remote service
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every retry after that
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Randomise each delay so that parallel workers don't retry in lockstep
+    pub jitter: bool,
+    pub retryable_statuses: Vec<StatusCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            retryable_statuses: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, initial_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff,
+            ..Default::default()
+        }
+    }
+
+    /// How long to wait after the given attempt (counting from 1) has failed
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff);
+
+        if self.jitter {
+            // "Equal jitter": always wait at least half the delay
+            let half = delay / 2;
+            half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+        } else {
+            delay
+        }
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    /// Whether an error from reqwest was a transport problem worth retrying,
+    /// rather than something wrong with the request itself.
+    pub fn is_retryable_error(&self, error: &reqwest::Error) -> bool {
+        error.is_connect() || error.is_timeout()
+    }
+
+    /// How long to wait after the given attempt failed with this response. The server's
+    /// Retry-After is respected, but never waited on for longer than max_backoff.
+    pub fn delay_after_response(&self, attempt: u32, headers: &HeaderMap) -> Duration {
+        match retry_after(headers) {
+            Some(delay) => delay.min(self.max_backoff),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+pub fn parse_status_code(s: &str) -> Result<StatusCode, String> {
+    s.trim()
+        .parse::<StatusCode>()
+        .map_err(|e| format!("'{s}' isn't an HTTP status code: {e}"))
+}
+
+/// Parse a Retry-After header, which is either a number of seconds or an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means we can go right away
+    Some(
+        date.with_timezone(&Utc)
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn policy_without_jitter() -> RetryPolicy {
+        RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::new(5, Duration::from_millis(100))
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_each_attempt() {
+        let policy = policy_without_jitter();
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = policy_without_jitter();
+        assert_eq!(policy.backoff(30), policy.max_backoff);
+        assert_eq!(policy.backoff(u32::MAX), policy.max_backoff);
+    }
+
+    #[test]
+    fn jittered_backoff_stays_within_bounds() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100));
+        for _ in 0..100 {
+            let delay = policy.backoff(2);
+            assert!(
+                delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200),
+                "Delay {delay:?} outside of expected range"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn retry_after_is_capped_at_max_backoff() {
+        let policy = policy_without_jitter();
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(policy.delay_after_response(1, &headers), policy.max_backoff);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("2"));
+        assert_eq!(
+            policy.delay_after_response(1, &headers),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay_after_response(1, &HeaderMap::new()),
+            Duration::from_millis(100)
+        );
+    }
+
+    #[test]
+    fn parses_retry_after_date_in_the_past() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+}
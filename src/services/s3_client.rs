@@ -1,19 +1,93 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
-use aws_sdk_s3::{config, config::Region, primitives::ByteStream, Client};
-use aws_smithy_http::body::SdkBody;
+use aws_sdk_s3::{
+    config,
+    config::Region,
+    error::{ProvideErrorMetadata, SdkError},
+    operation::{
+        abort_multipart_upload::AbortMultipartUploadError,
+        complete_multipart_upload::CompleteMultipartUploadError,
+        create_multipart_upload::CreateMultipartUploadError,
+        list_multipart_uploads::ListMultipartUploadsError, list_parts::ListPartsError,
+        put_object::PutObjectError, upload_part::UploadPartError,
+    },
+    primitives::ByteStream,
+    types::{self, CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use aws_smithy_http::{body::SdkBody, byte_stream::Length};
+use futures::{stream, StreamExt, TryStreamExt};
 
-use crate::model::file_metadata::FileMetadata;
+use crate::model::{
+    checksum::{
+        s3_checksums_match, Checksum, ChecksumAlgorithm, FileChecksums, PartChecksums,
+        UploadChecksum,
+    },
+    cli_error::CliError,
+    file_metadata::FileMetadata,
+};
 
 use super::aws::build_credentials_provider;
 
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+pub const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct MultipartConfig {
+    /// Files of at least this many bytes are uploaded in parts
+    pub threshold: u64,
+    pub part_size: u64,
+    /// Number of parts of a single file uploaded at once
+    pub concurrency: usize,
+}
+
 pub struct S3Client {
     client: Client,
     bucket_name: String,
+    multipart: MultipartConfig,
+    checksum_algorithm: ChecksumAlgorithm,
+}
+
+struct UploadedPart {
+    size: u64,
+    e_tag: String,
+    // Missing if the upload was started without this checksum algorithm
+    checksum: Option<String>,
+}
+
+/// Attach a checksum to a PutObject or UploadPart request, for S3 to verify the body against
+macro_rules! with_checksum {
+    ($request:expr, $checksum:expr) => {{
+        let checksum: &Checksum = $checksum;
+        match checksum.algorithm {
+            ChecksumAlgorithm::Sha256 => $request.checksum_sha256(checksum.header_value()),
+            ChecksumAlgorithm::Crc32c => $request.checksum_crc32_c(checksum.header_value()),
+            ChecksumAlgorithm::Md5 => $request.content_md5(checksum.header_value()),
+        }
+    }};
+}
+
+/// The checksum S3 reports for an upload, in the same algorithm as the one we sent.
+/// There isn't one for MD5, see verify_checksum.
+macro_rules! returned_checksum {
+    ($output:expr, $checksum:expr) => {
+        match $checksum.algorithm {
+            ChecksumAlgorithm::Sha256 => $output.checksum_sha256(),
+            ChecksumAlgorithm::Crc32c => $output.checksum_crc32_c(),
+            ChecksumAlgorithm::Md5 => None,
+        }
+    };
 }
 
 impl S3Client {
-    pub async fn new(bucket_name: &str, region: String, profile: Option<String>) -> Self {
+    pub async fn new(
+        bucket_name: &str,
+        region: String,
+        profile: Option<String>,
+        multipart: MultipartConfig,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> Self {
         let region_provider = Region::new(region);
         let credentials_provider = build_credentials_provider(profile).await;
 
@@ -27,6 +101,8 @@ impl S3Client {
         S3Client {
             client,
             bucket_name: bucket_name.to_owned(),
+            multipart,
+            checksum_algorithm,
         }
     }
 
@@ -35,6 +111,8 @@ impl S3Client {
         bucket_name: &str,
         region: String,
         profile: Option<String>,
+        multipart: MultipartConfig,
+        checksum_algorithm: ChecksumAlgorithm,
     ) -> Self {
         let region_provider = Region::new(region);
         let credentials_provider = build_credentials_provider(profile).await;
@@ -50,34 +128,482 @@ impl S3Client {
         S3Client {
             client,
             bucket_name: bucket_name.to_owned(),
+            multipart,
+            checksum_algorithm,
         }
     }
 
-    pub async fn upload_file(&self, key: &str, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    /// The checksums to compute for an upload of a file this size, one for each part if it's
+    /// over the multipart threshold. They're worked out up front so that they can be computed
+    /// in the same read of the file as its Giant hash.
+    pub fn part_checksums(&self, size: u64) -> anyhow::Result<PartChecksums> {
+        let part_size = if size >= self.multipart.threshold {
+            Some(part_layout(self.multipart.part_size, size)?.0)
+        } else {
+            None
+        };
+        Ok(PartChecksums::new(self.checksum_algorithm, part_size))
+    }
+
+    /// Upload a file, in parts if its checksums were computed in parts. If an earlier attempt
+    /// left an incomplete multipart upload at this key, the parts it finished are reused
+    /// as long as they still match the file. S3 checks what it received against our
+    /// checksum, and other than for MD5 we check the checksum it reports back against our own.
+    pub async fn upload_file(
+        &self,
+        key: &str,
+        path: impl AsRef<Path>,
+        checksums: &FileChecksums,
+    ) -> anyhow::Result<UploadChecksum> {
+        let size = tokio::fs::metadata(&path).await?.len();
+        if size != checksums.size {
+            anyhow::bail!(
+                "{} changed size from {} to {size} bytes since it was hashed",
+                path.as_ref().display(),
+                checksums.size
+            );
+        }
+
+        let checksum = match (checksums.part_size, checksums.parts.as_slice()) {
+            (None, [checksum]) => checksum,
+            (Some(part_size), parts) => {
+                return self
+                    .upload_file_multipart(key, path.as_ref(), size, part_size, parts)
+                    .await
+            }
+            (None, _) => anyhow::bail!("A file uploaded in one go should have one checksum"),
+        };
+
         let body = ByteStream::from_path(path).await?;
-        self.client
+        let request = self
+            .client
             .put_object()
             .bucket(&self.bucket_name)
             .key(key)
-            .body(body)
+            .body(body);
+        let output = with_checksum!(request, checksum).send().await?;
+
+        verify_checksum(key, checksum, returned_checksum!(output, checksum))?;
+
+        Ok(UploadChecksum {
+            algorithm: checksum.algorithm,
+            checksum: checksum.s3_value(),
+            e_tag: output.e_tag().unwrap_or_default().to_owned(),
+        })
+    }
+
+    async fn upload_file_multipart(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        part_size: u64,
+        part_checksums: &[Checksum],
+    ) -> anyhow::Result<UploadChecksum> {
+        let part_count = part_checksums.len() as u64;
+
+        let incomplete = match self.find_incomplete_upload(key).await? {
+            Some(upload_id) => {
+                let parts = self.list_uploaded_parts(key, &upload_id).await?;
+
+                if can_reuse_parts(&parts, part_size, size) {
+                    eprintln!(
+                        "Resuming upload of {} with {} of {} parts already uploaded",
+                        path.display(),
+                        parts.len(),
+                        part_count
+                    );
+                    Some((upload_id, parts))
+                } else {
+                    self.abort_upload(key, &upload_id).await?;
+                    None
+                }
+            }
+            None => None,
+        };
+        let (upload_id, uploaded_parts) = match incomplete {
+            Some(incomplete) => incomplete,
+            None => (self.create_upload(key).await?, HashMap::new()),
+        };
+
+        let mut parts = stream::iter(1..=part_count as i32)
+            .map(|number| {
+                let upload_id = &upload_id;
+                let uploaded_parts = &uploaded_parts;
+                async move {
+                    let offset = (number as u64 - 1) * part_size;
+                    let length = expected_part_size(number, part_size, size);
+                    let checksum = part_checksums[number as usize - 1].clone();
+
+                    // Only reuse a part if it's still what's on disk
+                    if let Some(uploaded) = uploaded_parts.get(&number) {
+                        if uploaded.checksum.as_deref().is_some_and(|returned| {
+                            s3_checksums_match(&checksum.s3_value(), returned)
+                        }) {
+                            return anyhow::Ok((number, checksum, uploaded.e_tag.clone()));
+                        }
+                    }
+
+                    let body = ByteStream::read_from()
+                        .path(path)
+                        .offset(offset)
+                        .length(Length::Exact(length))
+                        .build()
+                        .await?;
+
+                    let request = self
+                        .client
+                        .upload_part()
+                        .bucket(&self.bucket_name)
+                        .key(key)
+                        .upload_id(upload_id)
+                        .part_number(number)
+                        .content_length(length as i64)
+                        .body(body);
+                    let output = with_checksum!(request, &checksum).send().await?;
+
+                    let part_key = format!("{key} part {number}");
+                    verify_checksum(&part_key, &checksum, returned_checksum!(output, checksum))?;
+
+                    let e_tag = output.e_tag().unwrap_or_default().to_owned();
+                    anyhow::Ok((number, checksum, e_tag))
+                }
+            })
+            .buffer_unordered(self.multipart.concurrency.max(1))
+            .try_collect::<Vec<(i32, Checksum, String)>>()
+            .await?;
+        parts.sort_by_key(|(number, _, _)| *number);
+
+        let completed_parts: Vec<CompletedPart> = parts
+            .iter()
+            .map(|(number, checksum, e_tag)| {
+                let part = CompletedPart::builder().part_number(*number).e_tag(e_tag);
+                match checksum.algorithm {
+                    ChecksumAlgorithm::Sha256 => part.checksum_sha256(checksum.header_value()),
+                    ChecksumAlgorithm::Crc32c => part.checksum_crc32_c(checksum.header_value()),
+                    ChecksumAlgorithm::Md5 => part,
+                }
+                .build()
+            })
+            .collect();
+
+        let output = self
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
             .send()
             .await?;
 
-        Ok(())
+        let part_checksums: Vec<Checksum> = parts.into_iter().map(|(_, c, _)| c).collect();
+        let checksum = self.checksum_algorithm.of_parts(&part_checksums);
+        let expected = format!("{}-{}", checksum.s3_value(), part_checksums.len());
+        verify_checksum(key, &checksum, returned_checksum!(output, checksum))?;
+
+        Ok(UploadChecksum {
+            algorithm: checksum.algorithm,
+            checksum: expected,
+            e_tag: output.e_tag().unwrap_or_default().to_owned(),
+        })
     }
 
-    pub async fn upload_metadata(&self, key: &str, metadata: FileMetadata) -> anyhow::Result<()> {
-        let json = serde_json::to_string(&metadata)?;
-        let body = ByteStream::new(SdkBody::from(&*json));
+    async fn create_upload(&self, key: &str) -> anyhow::Result<String> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .set_checksum_algorithm(match self.checksum_algorithm {
+                ChecksumAlgorithm::Sha256 => Some(types::ChecksumAlgorithm::Sha256),
+                ChecksumAlgorithm::Crc32c => Some(types::ChecksumAlgorithm::Crc32C),
+                // Content-MD5 is per request, S3 doesn't need telling up front
+                ChecksumAlgorithm::Md5 => None,
+            })
+            .send()
+            .await?;
+
+        output
+            .upload_id()
+            .map(|id| id.to_owned())
+            .ok_or_else(|| anyhow::anyhow!("S3 did not return an upload ID for {key}"))
+    }
+
+    /// Find the most recently started multipart upload for exactly this key, if there is one
+    async fn find_incomplete_upload(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let output = self
+            .client
+            .list_multipart_uploads()
+            .bucket(&self.bucket_name)
+            .prefix(key)
+            .send()
+            .await?;
+
+        let upload_id = output
+            .uploads()
+            .unwrap_or_default()
+            .iter()
+            .filter(|upload| upload.key() == Some(key))
+            .max_by_key(|upload| upload.initiated().map(|t| (t.secs(), t.subsec_nanos())))
+            .and_then(|upload| upload.upload_id())
+            .map(|id| id.to_owned());
+
+        Ok(upload_id)
+    }
+
+    /// Returns every uploaded part, by part number
+    async fn list_uploaded_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> anyhow::Result<HashMap<i32, UploadedPart>> {
+        let mut parts = HashMap::new();
+        let mut marker: Option<String> = None;
+
+        loop {
+            let output = self
+                .client
+                .list_parts()
+                .bucket(&self.bucket_name)
+                .key(key)
+                .upload_id(upload_id)
+                .set_part_number_marker(marker)
+                .send()
+                .await?;
+
+            for part in output.parts().unwrap_or_default() {
+                if let Some(e_tag) = part.e_tag() {
+                    let checksum = match self.checksum_algorithm {
+                        ChecksumAlgorithm::Sha256 => part.checksum_sha256(),
+                        ChecksumAlgorithm::Crc32c => part.checksum_crc32_c(),
+                        // Not the MD5 under SSE-KMS, in which case the part doesn't
+                        // match and is just uploaded again
+                        ChecksumAlgorithm::Md5 => Some(e_tag),
+                    };
+                    parts.insert(
+                        part.part_number(),
+                        UploadedPart {
+                            size: part.size() as u64,
+                            e_tag: e_tag.to_owned(),
+                            checksum: checksum.map(|c| c.to_owned()),
+                        },
+                    );
+                }
+            }
 
+            if output.is_truncated() {
+                marker = output.next_part_number_marker().map(|m| m.to_owned());
+            } else {
+                return Ok(parts);
+            }
+        }
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> anyhow::Result<()> {
         self.client
-            .put_object()
+            .abort_multipart_upload()
             .bucket(&self.bucket_name)
             .key(key)
-            .body(body)
+            .upload_id(upload_id)
             .send()
             .await?;
 
         Ok(())
     }
+
+    /// Clean up an incomplete multipart upload so its parts stop costing money.
+    /// Does nothing if there isn't one.
+    pub async fn abort_incomplete_upload(&self, key: &str) -> anyhow::Result<()> {
+        if let Some(upload_id) = self.find_incomplete_upload(key).await? {
+            self.abort_upload(key, &upload_id).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn upload_metadata(&self, key: &str, metadata: &FileMetadata) -> anyhow::Result<()> {
+        let json = serde_json::to_string(metadata)?;
+        let checksum = self.checksum_algorithm.of_bytes(json.as_bytes());
+        let body = ByteStream::new(SdkBody::from(&*json));
+
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .body(body);
+        let output = with_checksum!(request, &checksum).send().await?;
+
+        verify_checksum(key, &checksum, returned_checksum!(output, checksum))?;
+
+        Ok(())
+    }
+}
+
+/// Whether an error returned from an upload is likely to go away if we try again,
+/// e.g. throttling or a dropped connection, as opposed to bad credentials or an unreadable file.
+pub fn is_transient_error(error: &anyhow::Error) -> bool {
+    macro_rules! check_sdk_errors {
+        ($($operation_error:ty),*) => {
+            $(
+                if let Some(sdk_error) =
+                    error.downcast_ref::<SdkError<$operation_error, http::Response<SdkBody>>>()
+                {
+                    return is_transient_sdk_error(sdk_error);
+                }
+            )*
+        };
+    }
+
+    check_sdk_errors!(
+        PutObjectError,
+        CreateMultipartUploadError,
+        UploadPartError,
+        CompleteMultipartUploadError,
+        ListMultipartUploadsError,
+        ListPartsError,
+        AbortMultipartUploadError
+    );
+
+    // Including a checksum mismatch, since S3 already rejects bodies which don't match the
+    // checksum we sent. Reporting a different one means something's wrong that retrying won't fix.
+    false
+}
+
+fn is_transient_sdk_error<E: ProvideErrorMetadata>(
+    error: &SdkError<E, http::Response<SdkBody>>,
+) -> bool {
+    match error {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        SdkError::ServiceError(service_error) => {
+            let status = service_error.raw().status();
+            status.is_server_error()
+                || status == http::StatusCode::TOO_MANY_REQUESTS
+                || matches!(
+                    service_error.err().code(),
+                    Some(
+                        "SlowDown"
+                            | "RequestTimeout"
+                            | "InternalError"
+                            | "ServiceUnavailable"
+                            // The body was corrupted on the way to S3
+                            | "BadDigest"
+                    )
+                )
+        }
+        _ => false,
+    }
+}
+
+fn verify_checksum(key: &str, expected: &Checksum, returned: Option<&str>) -> Result<(), CliError> {
+    // S3 rejects a body that doesn't match its Content-MD5, and doesn't report the MD5 back.
+    // The ETag only happens to be the MD5 without SSE-KMS, and isn't for multipart uploads.
+    if expected.algorithm == ChecksumAlgorithm::Md5 {
+        return Ok(());
+    }
+
+    let expected_value = expected.s3_value();
+    match returned {
+        Some(returned) if s3_checksums_match(&expected_value, returned) => Ok(()),
+        Some(returned) => Err(CliError::ChecksumMismatch {
+            key: key.to_owned(),
+            expected: expected_value,
+            returned: returned.to_owned(),
+        }),
+        None => Err(CliError::ChecksumMissing(
+            expected.algorithm,
+            key.to_owned(),
+        )),
+    }
+}
+
+/// The size of each part of a multipart upload of `file_size` bytes, and how many there are.
+/// S3 caps the number of parts, so very large files need bigger parts than configured.
+fn part_layout(configured_part_size: u64, file_size: u64) -> anyhow::Result<(u64, u64)> {
+    let part_size = configured_part_size
+        .clamp(MIN_PART_SIZE, MAX_PART_SIZE)
+        .max(file_size.div_ceil(MAX_PARTS));
+    if part_size > MAX_PART_SIZE {
+        anyhow::bail!(
+            "A file of {file_size} bytes is too large for S3, which allows at most {MAX_PARTS} parts of 5 GiB"
+        );
+    }
+    Ok((part_size, file_size.div_ceil(part_size).max(1)))
+}
+
+fn expected_part_size(part_number: i32, part_size: u64, file_size: u64) -> u64 {
+    let offset = (part_number as u64 - 1) * part_size;
+    part_size.min(file_size.saturating_sub(offset))
+}
+
+/// Parts from a run with a different part size or checksum can't be reused
+fn can_reuse_parts(parts: &HashMap<i32, UploadedPart>, part_size: u64, file_size: u64) -> bool {
+    parts.iter().all(|(number, part)| {
+        part.size == expected_part_size(*number, part_size, file_size) && part.checksum.is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn part(size: u64) -> UploadedPart {
+        UploadedPart {
+            size,
+            e_tag: "\"e-tag\"".into(),
+            checksum: Some("checksum".into()),
+        }
+    }
+
+    #[test]
+    fn parts_are_the_configured_size_until_there_would_be_too_many() {
+        assert_eq!(part_layout(64 * MIB, 200 * MIB).unwrap(), (64 * MIB, 4));
+        assert_eq!(part_layout(64 * MIB, 128 * MIB).unwrap(), (64 * MIB, 2));
+        // Below S3's minimum
+        assert_eq!(part_layout(MIB, 20 * MIB).unwrap(), (5 * MIB, 4));
+
+        let huge = 1024 * 1024 * MIB;
+        let (part_size, part_count) = part_layout(64 * MIB, huge).unwrap();
+        assert_eq!(part_size, huge.div_ceil(MAX_PARTS));
+        assert_eq!(part_count, MAX_PARTS);
+
+        assert!(part_layout(64 * MIB, MAX_PARTS * MAX_PART_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn last_part_is_whatever_is_left() {
+        assert_eq!(expected_part_size(1, 64 * MIB, 100 * MIB), 64 * MIB);
+        assert_eq!(expected_part_size(2, 64 * MIB, 100 * MIB), 36 * MIB);
+        assert_eq!(expected_part_size(3, 64 * MIB, 100 * MIB), 0);
+    }
+
+    #[test]
+    fn only_reuses_parts_of_the_same_size_with_checksums() {
+        let size = 100 * MIB;
+        let parts = HashMap::from([(1, part(64 * MIB)), (2, part(36 * MIB))]);
+        assert!(can_reuse_parts(&parts, 64 * MIB, size));
+        // From a run with a different part size
+        assert!(!can_reuse_parts(&parts, 32 * MIB, size));
+
+        let beyond_the_end = HashMap::from([(3, part(MIB))]);
+        assert!(!can_reuse_parts(&beyond_the_end, 64 * MIB, size));
+
+        let without_checksum = HashMap::from([(
+            1,
+            UploadedPart {
+                checksum: None,
+                ..part(64 * MIB)
+            },
+        )]);
+        assert!(!can_reuse_parts(&without_checksum, 64 * MIB, size));
+    }
 }